@@ -1,4 +1,5 @@
 mod output;
+mod post;
 mod render;
 mod scenes;
 mod utils;
@@ -6,13 +7,40 @@ mod utils;
 // INPUT AND OUTPUT SETTINGS
 use scenes::scene1 as scene;
 const OUTPUT_LOCATION: &str = "renders/r2.ppm";
+// denoised renders also write the noisy image here if set to keep it
+const RAW_LOCATION: &str = "renders/r2.raw.ppm";
+// maps the rendered colors to the display, after scaling them by the exposure
+// in stops; --tonemapper <name> and --exposure <stops> override these
+const TONEMAPPER: Tonemapper = Tonemapper::Aces;
+const EXPOSURE: f32 = 0.0;
+// progressive renders write the image so far here between passes
+const PREVIEW_LOCATION: &str = "renders/preview.ppm";
+// if set, progressive renders save their progress here after every pass; run
+// with --resume to continue from it
+const CHECKPOINT_LOCATION: Option<&str> = None;
+// AOVs are written as float images named after this and the AOV
+const AOV_LOCATION: &str = "renders/r2";
+// the beauty pass and all AOVs as layers of one EXR, with render metadata
+const EXR_LOCATION: Option<&str> = Some("renders/r2.exr");
+// render statistics as JSON, for comparing performance between commits
+const STATS_LOCATION: Option<&str> = None;
+// grayscale map of how many samples adaptive sampling spent on each pixel
+const SAMPLE_HEATMAP_LOCATION: Option<&str> = None;
 
 // dependencies
 
-use output::{ppm::PPMGenerator, tonemapping, ImageGenerator};
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
-use render::sampler::Sampler;
-use std::{path::Path, sync::mpsc::channel, time::Instant};
+use output::{
+    exr::EXRGenerator, pfm::PFMGenerator, ppm::PPMGenerator, tonemapping::Tonemapper,
+    ImageGenerator,
+};
+use post::{denoise, effects, grade::Grade, Image};
+use render::{film::Film, renderer, sampler::Sampler, stats};
+use scenes::RenderSettings;
+use std::{fs, path::Path, time::Instant};
+use utils::{
+    color_space::{transform, ColorSpace},
+    vector::Color,
+};
 
 fn main() {
     let start = Instant::now();
@@ -23,35 +51,234 @@ fn main() {
 
 fn render() {
     let render_settings = scene::get_render_settings();
+    render_settings.check();
     let img_size = render_settings.img_size;
 
-    let output_path = Path::new(OUTPUT_LOCATION);
-    let mut ppm_generator = PPMGenerator::new(output_path, img_size);
+    let tonemapper = match arg_value("--tonemapper") {
+        Some(name) => match Tonemapper::from_name(&name) {
+            Some(tonemapper) => tonemapper,
+            None => panic!("unknown tonemapper {}", name),
+        },
+        None => TONEMAPPER,
+    };
+    let exposure = match arg_value("--exposure") {
+        Some(stops) => match stops.parse() {
+            Ok(stops) => stops,
+            Err(why) => panic!("couldn't parse exposure {}: {}", stops, why),
+        },
+        None => EXPOSURE,
+    };
+    // the display shows sRGB, which has Rec.709 primaries
+    let to_display = render_settings
+        .working_space
+        .conversion_to(ColorSpace::Rec709);
+    let grade = Grade::new(render_settings.grade);
+    effects::check(render_settings.effects);
+    let display = |c| grade.apply(tonemapper.apply(transform(&to_display, c), exposure));
+    let write_image = |image: &Image, path| write_image(image, display, path);
 
     let scene = scene::generate();
     let sampler = &Sampler::new(scene);
 
-    let (s, r) = channel();
+    // the sample pattern is laid out for the most samples a pixel can get
+    let sequence = (render_settings.sample_pattern, render_settings.max_samples());
+    let resume_from = if std::env::args().any(|arg| arg == "--resume") {
+        match CHECKPOINT_LOCATION {
+            Some(checkpoint_location) => Some(Film::read_checkpoint(
+                Path::new(checkpoint_location),
+                img_size,
+                render_settings.filter,
+                &render_settings.film_aovs(),
+                render_settings.seed,
+                sequence,
+            )),
+            None => panic!("--resume needs a checkpoint location"),
+        }
+    } else {
+        None
+    };
 
-    (0..img_size.1).into_par_iter().for_each_with(s, |s, y| {
-        for x in 0..img_size.0 {
-            let sampled_color = sampler.sample((x, y), render_settings.samples_per_pixel);
-            let tonemapped = tonemapping::aces(sampled_color);
+    let render_start = Instant::now();
+    let (film, stop_reason) = renderer::render(
+        sampler,
+        &render_settings,
+        resume_from,
+        |film| write_image(&exposed(sampler, film), Path::new(PREVIEW_LOCATION)),
+        |film, sample_limit| {
+            if let Some(checkpoint_location) = CHECKPOINT_LOCATION {
+                let path = Path::new(checkpoint_location);
+                film.write_checkpoint(path, render_settings.seed, sequence, sample_limit);
+            }
+        },
+    );
+
+    let render_seconds = render_start.elapsed().as_secs_f64();
+    println!("stopped rendering: {}", stop_reason);
+
+    let render_stats = stats::totals();
+    println!("{}", render_stats.report(render_seconds));
+    if let Some(stats_location) = STATS_LOCATION {
+        match fs::write(stats_location, render_stats.to_json(render_seconds)) {
+            Err(why) => panic!("couldn't write to {}: {}", stats_location, why),
+            Ok(_) => println!("successfully wrote to {}", stats_location),
+        }
+    }
+
+    let raw = exposed(sampler, &film);
+    let (beauty, raw) = match render_settings.denoise {
+        Some(denoise_settings) => {
+            let denoised =
+                denoise::denoise(&raw, &film, denoise_settings, render_settings.working_space);
+            (denoised, Some(raw).filter(|_| denoise_settings.keep_raw))
+        }
+        None => (raw, None),
+    };
+    // lens and film effects only go into the images meant for viewing, the
+    // EXR keeps the clean render for compositing
+    let with_effects = |image| {
+        let aperture = sampler.camera().aperture();
+        let (effects, space) = (render_settings.effects, render_settings.working_space);
+        effects::apply(image, effects, space, aperture, render_settings.seed)
+    };
+    write_image(&with_effects(&beauty), Path::new(OUTPUT_LOCATION));
+    if let Some(raw) = &raw {
+        write_image(&with_effects(raw), Path::new(RAW_LOCATION));
+    }
+
+    for (index, aov) in film.aovs().iter().enumerate() {
+        let path = format!("{}.{}.pfm", AOV_LOCATION, aov.name());
+        let mut pfm_generator = PFMGenerator::new(Path::new(&path), img_size);
+        for y in 0..img_size.1 {
+            for x in 0..img_size.0 {
+                pfm_generator.set_pixel((x, y), aov_pixel(sampler, &film, index, (x, y)));
+            }
+        }
+        pfm_generator.write();
+    }
+
+    if let Some(exr_location) = EXR_LOCATION {
+        let path = Path::new(exr_location);
+        write_exr(
+            sampler,
+            &film,
+            (&beauty, raw.as_ref()),
+            &render_settings,
+            render_seconds,
+            path,
+        );
+    }
+
+    if let Some(heatmap_location) = SAMPLE_HEATMAP_LOCATION {
+        let max_samples = render_settings.max_samples();
+        let mut heatmap = PPMGenerator::new(Path::new(heatmap_location), img_size);
+        for y in 0..img_size.1 {
+            for x in 0..img_size.0 {
+                let level = (film.sample_count((x, y)) * 255 / max_samples) as u8;
+                heatmap.set_pixel((x, y), (level, level, level));
+            }
+        }
+        heatmap.write();
+    }
+}
+
+/// Value following `flag` on the command line, if it's there.
+fn arg_value(flag: &str) -> Option<String> {
+    let args: Vec<String> = std::env::args().collect();
+    let index = args.iter().position(|arg| arg == flag)?;
+    match args.get(index + 1) {
+        Some(value) => Some(value.clone()),
+        None => panic!("{} needs a value", flag),
+    }
+}
+
+/// The film's pixels as the camera records them.
+fn exposed<const N: usize>(sampler: &Sampler<N>, film: &Film) -> Image {
+    Image::new(film.size(), |pos| sampler.expose(film.pixel(pos)))
+}
+
+/// Writes `image` as it looks on the display, which `display` maps its colors
+/// to.
+fn write_image(image: &Image, display: impl Fn(Color) -> Color, path: &'static Path) {
+    let mut ppm_generator = PPMGenerator::new(path, image.size);
+
+    for y in 0..image.size.1 {
+        for x in 0..image.size.0 {
+            let tonemapped = display(image.get((x, y)));
             let compressed = (
                 (tonemapped.x * 255.0) as u8,
                 (tonemapped.y * 255.0) as u8,
                 (tonemapped.z * 255.0) as u8,
             );
-            s.send(((x, y), compressed)).unwrap();
+            ppm_generator.set_pixel((x, y), compressed);
         }
-    });
+    }
 
-    // collect messages
+    ppm_generator.write();
+}
 
-    let responses: Vec<_> = r.iter().collect();
-    for (pos, col) in responses {
-        ppm_generator.set_pixel(pos, col);
+/// An AOV's pixel as it gets written. Light AOVs are exposed like the beauty
+/// pass, so they still add up to it.
+fn aov_pixel<const N: usize>(
+    sampler: &Sampler<N>,
+    film: &Film,
+    index: usize,
+    pos: (u32, u32),
+) -> Color {
+    let value = film.aov(index, pos);
+    if film.aovs()[index].is_light() {
+        sampler.expose(value)
+    } else {
+        value
     }
+}
 
-    ppm_generator.write();
+/// Writes `images`, the final beauty pass and optionally the raw one, along
+/// with the film's AOVs.
+fn write_exr<const N: usize>(
+    sampler: &Sampler<N>,
+    film: &Film,
+    images: (&Image, Option<&Image>),
+    render_settings: &RenderSettings,
+    render_seconds: f64,
+    path: &Path,
+) {
+    let img_size = render_settings.img_size;
+    let mut exr_generator = EXRGenerator::new(path, img_size);
+
+    let (beauty, raw) = images;
+    exr_generator.add_layer("", &["R", "G", "B"], |pos| beauty.get(pos));
+    if let Some(raw) = raw {
+        exr_generator.add_layer("raw", &["R", "G", "B"], |pos| raw.get(pos));
+    }
+    for (index, aov) in film.aovs().iter().enumerate() {
+        exr_generator.add_layer(aov.name(), aov.channels(), |pos| {
+            aov_pixel(sampler, film, index, pos)
+        });
+    }
+
+    exr_generator.add_chromaticities(render_settings.working_space.chromaticities());
+
+    let camera = sampler.camera();
+    exr_generator.add_matrix("worldToCamera", camera.world_to_camera());
+    if let Some(world_to_ndc) = camera.world_to_ndc() {
+        exr_generator.add_matrix("worldToNDC", world_to_ndc);
+    }
+
+    let mut samples = 0;
+    for y in 0..img_size.1 {
+        for x in 0..img_size.0 {
+            samples += film.sample_count((x, y)) as u64;
+        }
+    }
+    let mean_samples = samples as f64 / (img_size.0 * img_size.1) as f64;
+    exr_generator.add_int("samplesPerPixel", render_settings.max_samples() as i32);
+    exr_generator.add_float("meanSamplesPerPixel", mean_samples as f32);
+    exr_generator.add_float("renderTime", render_seconds as f32);
+    exr_generator.add_string("seed", &render_settings.seed.to_string());
+    exr_generator.add_string(
+        "sceneHash",
+        &format!("{:016x}", scenes::source_hash(scene::SOURCE)),
+    );
+
+    exr_generator.write();
 }
@@ -0,0 +1,308 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use crate::utils::vector::*;
+
+const MAGIC: u32 = 20000630;
+/// Single part scanline file; `LONG_NAMES` allows names past 31 bytes.
+const VERSION: u32 = 2;
+const LONG_NAMES: u32 = 0x400;
+const PIXEL_TYPE_FLOAT: i32 = 2;
+
+/// Writes uncompressed multi-layer OpenEXR images with 32-bit float
+/// channels. Channels of a layer are named `layer.channel`; the default
+/// layer's channels, like the beauty pass's R, G and B, have no prefix.
+pub struct EXRGenerator {
+    path: PathBuf,
+    width: u32,
+    height: u32,
+    /// Channel names and their pixels, rows stored bottom to top.
+    channels: Vec<(String, Vec<f32>)>,
+    /// Header attributes as name, type and value bytes.
+    attributes: Vec<(String, &'static str, Vec<u8>)>,
+}
+
+impl EXRGenerator {
+    pub fn new(file_path: &Path, size: (u32, u32)) -> EXRGenerator {
+        return EXRGenerator {
+            path: file_path.to_path_buf(),
+            width: size.0,
+            height: size.1,
+            channels: Vec::new(),
+            attributes: Vec::new(),
+        };
+    }
+
+    /// Adds a layer with up to three channels, filled from the components of
+    /// `pixel` in order. Pass an empty `layer` for the default layer.
+    pub fn add_layer(
+        &mut self,
+        layer: &str,
+        channel_names: &[&str],
+        pixel: impl Fn((u32, u32)) -> Color,
+    ) {
+        let first = self.channels.len();
+        for name in channel_names {
+            let name = if layer.is_empty() {
+                name.to_string()
+            } else {
+                format!("{}.{}", layer, name)
+            };
+            let pixels = vec![0.0; (self.width * self.height) as usize];
+            self.channels.push((name, pixels));
+        }
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let color = pixel((x, y));
+                let index = (x + y * self.width) as usize;
+                for (i, v) in [color.x, color.y, color.z]
+                    .iter()
+                    .take(channel_names.len())
+                    .enumerate()
+                {
+                    self.channels[first + i].1[index] = *v;
+                }
+            }
+        }
+    }
+
+    pub fn add_int(&mut self, name: &str, value: i32) {
+        let bytes = value.to_le_bytes().to_vec();
+        self.attributes.push((name.to_string(), "int", bytes));
+    }
+
+    pub fn add_float(&mut self, name: &str, value: f32) {
+        let bytes = value.to_le_bytes().to_vec();
+        self.attributes.push((name.to_string(), "float", bytes));
+    }
+
+    pub fn add_string(&mut self, name: &str, value: &str) {
+        let bytes = value.as_bytes().to_vec();
+        self.attributes.push((name.to_string(), "string", bytes));
+    }
+
+    /// Tells readers which color space the image is in, by the xy
+    /// chromaticities of its red, green and blue primaries and white point.
+    pub fn add_chromaticities(&mut self, chromaticities: [(f32, f32); 4]) {
+        let bytes = chromaticities
+            .iter()
+            .flat_map(|(x, y)| [*x, *y])
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let name = "chromaticities".to_string();
+        self.attributes.push((name, "chromaticities", bytes));
+    }
+
+    /// Adds a 4x4 matrix, stored row by row.
+    pub fn add_matrix(&mut self, name: &str, matrix: [[f32; 4]; 4]) {
+        let bytes = matrix
+            .iter()
+            .flatten()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        self.attributes.push((name.to_string(), "m44f", bytes));
+    }
+
+    pub fn write(&self) {
+        let display = self.path.display();
+
+        let mut file = match File::create(&self.path) {
+            Err(why) => panic!("couldn't create {}: {}", display, why),
+            Ok(file) => file,
+        };
+
+        match file.write_all(&self.to_bytes()) {
+            Err(why) => panic!("couldn't write to {}: {}", display, why),
+            Ok(_) => println!("successfully wrote to {}", display),
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        // readers expect the channels in alphabetical order, both in the
+        // header and in every scanline
+        let mut channels: Vec<&(String, Vec<f32>)> = self.channels.iter().collect();
+        channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut channel_list = Vec::new();
+        for (name, _) in channels.iter() {
+            push_name(&mut channel_list, name);
+            channel_list.extend_from_slice(&PIXEL_TYPE_FLOAT.to_le_bytes());
+            // pLinear and three reserved bytes, then x and y sampling
+            channel_list.extend_from_slice(&[0; 4]);
+            channel_list.extend_from_slice(&1i32.to_le_bytes());
+            channel_list.extend_from_slice(&1i32.to_le_bytes());
+        }
+        channel_list.push(0);
+
+        let window: Vec<u8> = [0, 0, self.width as i32 - 1, self.height as i32 - 1]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let mut attributes = vec![
+            ("channels".to_string(), "chlist", channel_list),
+            ("compression".to_string(), "compression", vec![0]),
+            ("dataWindow".to_string(), "box2i", window.clone()),
+            ("displayWindow".to_string(), "box2i", window),
+            ("lineOrder".to_string(), "lineOrder", vec![0]),
+            (
+                "pixelAspectRatio".to_string(),
+                "float",
+                1.0f32.to_le_bytes().to_vec(),
+            ),
+            ("screenWindowCenter".to_string(), "v2f", vec![0; 8]),
+            (
+                "screenWindowWidth".to_string(),
+                "float",
+                1.0f32.to_le_bytes().to_vec(),
+            ),
+        ];
+        attributes.extend(self.attributes.iter().cloned());
+
+        let long_names = attributes.iter().any(|(name, _, _)| name.len() > 31)
+            || channels.iter().any(|(name, _)| name.len() > 31);
+        let version = if long_names {
+            VERSION | LONG_NAMES
+        } else {
+            VERSION
+        };
+
+        let mut out = Vec::new();
+        out.extend_from_slice(&MAGIC.to_le_bytes());
+        out.extend_from_slice(&version.to_le_bytes());
+        for (name, kind, value) in attributes.iter() {
+            push_name(&mut out, name);
+            push_name(&mut out, kind);
+            out.extend_from_slice(&(value.len() as i32).to_le_bytes());
+            out.extend_from_slice(value);
+        }
+        out.push(0);
+
+        // uncompressed files hold one scanline per chunk, each found through
+        // the offset table
+        let line_size = 4 * self.width as usize * channels.len();
+        let table_end = out.len() + 8 * self.height as usize;
+        for line in 0..self.height as usize {
+            let offset = (table_end + line * (8 + line_size)) as u64;
+            out.extend_from_slice(&offset.to_le_bytes());
+        }
+
+        for line in 0..self.height {
+            out.extend_from_slice(&(line as i32).to_le_bytes());
+            out.extend_from_slice(&(line_size as i32).to_le_bytes());
+            // EXR lines run top to bottom
+            let row = ((self.height - 1 - line) * self.width) as usize;
+            for (_, pixels) in channels.iter() {
+                for v in &pixels[row..row + self.width as usize] {
+                    out.extend_from_slice(&v.to_le_bytes());
+                }
+            }
+        }
+
+        return out;
+    }
+}
+
+fn push_name(out: &mut Vec<u8>, name: &str) {
+    out.extend_from_slice(name.as_bytes());
+    out.push(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    fn read_name(bytes: &[u8], at: &mut usize) -> String {
+        let end = *at + bytes[*at..].iter().position(|b| *b == 0).unwrap();
+        let name = String::from_utf8(bytes[*at..end].to_vec()).unwrap();
+        *at = end + 1;
+        name
+    }
+
+    fn read_u32(bytes: &[u8], at: &mut usize) -> u32 {
+        let v = u32::from_le_bytes(bytes[*at..*at + 4].try_into().unwrap());
+        *at += 4;
+        v
+    }
+
+    fn read_f32(bytes: &[u8], at: &mut usize) -> f32 {
+        f32::from_bits(read_u32(bytes, at))
+    }
+
+    /// Header attributes by name, with the offset just past the header.
+    fn read_header(bytes: &[u8]) -> (Vec<(String, String, Vec<u8>)>, usize) {
+        let mut at = 8;
+        let mut attributes = Vec::new();
+        while bytes[at] != 0 {
+            let name = read_name(bytes, &mut at);
+            let kind = read_name(bytes, &mut at);
+            let len = read_u32(bytes, &mut at) as usize;
+            attributes.push((name, kind, bytes[at..at + len].to_vec()));
+            at += len;
+        }
+        (attributes, at + 1)
+    }
+
+    #[test]
+    fn writes_a_readable_scanline_file() {
+        let mut exr = EXRGenerator::new(Path::new("unused.exr"), (2, 1));
+        exr.add_layer("", &["R", "G", "B"], |(x, _)| {
+            Color::new(x as f32 + 0.25, 2.0, 3.0)
+        });
+        exr.add_layer("depth", &["Z"], |(x, _)| {
+            Color::new(10.0 + x as f32, 0.0, 0.0)
+        });
+        exr.add_string("seed", "7");
+        let bytes = exr.to_bytes();
+
+        let mut at = 0;
+        assert_eq!(read_u32(&bytes, &mut at), MAGIC);
+        assert_eq!(read_u32(&bytes, &mut at), VERSION);
+
+        let (attributes, header_end) = read_header(&bytes);
+        let attribute = |name: &str| {
+            attributes
+                .iter()
+                .find(|(n, _, _)| n == name)
+                .map(|(_, kind, value)| (kind.as_str(), value.clone()))
+                .unwrap()
+        };
+        assert_eq!(attribute("seed"), ("string", b"7".to_vec()));
+        assert_eq!(attribute("compression"), ("compression", vec![0]));
+
+        let (kind, channel_list) = attribute("channels");
+        assert_eq!(kind, "chlist");
+        let mut names = Vec::new();
+        let mut at = 0;
+        while channel_list[at] != 0 {
+            names.push(read_name(&channel_list, &mut at));
+            assert_eq!(read_u32(&channel_list, &mut at) as i32, PIXEL_TYPE_FLOAT);
+            at += 12;
+        }
+        assert_eq!(names, ["B", "G", "R", "depth.Z"]);
+
+        // one scanline, right after its offset
+        let mut at = header_end;
+        let offset = u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap()) as usize;
+        assert_eq!(offset, header_end + 8);
+        at = offset;
+        assert_eq!(read_u32(&bytes, &mut at), 0);
+        assert_eq!(read_u32(&bytes, &mut at), 4 * 2 * 4);
+        let pixels: Vec<f32> = (0..8).map(|_| read_f32(&bytes, &mut at)).collect();
+        assert_eq!(pixels, [3.0, 3.0, 2.0, 2.0, 0.25, 1.25, 10.0, 11.0]);
+        assert_eq!(at, bytes.len());
+    }
+
+    #[test]
+    fn long_names_set_the_version_flag() {
+        let mut exr = EXRGenerator::new(Path::new("unused.exr"), (1, 1));
+        exr.add_layer("a_layer_with_a_rather_long_name", &["R"], |_| {
+            Color::new(1.0, 0.0, 0.0)
+        });
+        let bytes = exr.to_bytes();
+        let mut at = 4;
+        assert_eq!(read_u32(&bytes, &mut at), VERSION | LONG_NAMES);
+    }
+}
@@ -1,5 +1,7 @@
 use crate::utils::vector::Color;
 
+pub mod exr;
+pub mod pfm;
 pub mod ppm;
 pub mod tonemapping;
 
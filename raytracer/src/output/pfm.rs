@@ -0,0 +1,60 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use crate::utils::vector::*;
+
+/// Writes linear float images as Portable Float Maps, for data that
+/// shouldn't be tonemapped or quantized, like AOVs.
+pub struct PFMGenerator {
+    path: PathBuf,
+    width: u32,
+    height: u32,
+    pixel_array: Vec<Color>,
+}
+
+impl PFMGenerator {
+    pub fn new(file_path: &Path, size: (u32, u32)) -> PFMGenerator {
+        let array = vec![Color::new(0.0, 0.0, 0.0); (size.0 * size.1) as usize];
+
+        return PFMGenerator {
+            path: file_path.to_path_buf(),
+            width: size.0,
+            height: size.1,
+            pixel_array: array,
+        };
+    }
+
+    pub fn set_pixel(&mut self, coord_xy: (u32, u32), color: Color) {
+        // rows are stored bottom to top, same as the film
+        let index = coord_xy.0 + coord_xy.1 * self.width;
+        self.pixel_array[index as usize] = color;
+    }
+
+    pub fn write(&self) {
+        let display = self.path.display();
+
+        let mut file = match File::create(&self.path) {
+            Err(why) => panic!("couldn't create {}: {}", display, why),
+            Ok(file) => file,
+        };
+
+        match file.write_all(&self.to_bytes()) {
+            Err(why) => panic!("couldn't write to {}: {}", display, why),
+            Ok(_) => println!("successfully wrote to {}", display),
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        // a negative scale marks little endian data
+        let mut out = format!("PF\n{} {}\n-1.0\n", self.width, self.height).into_bytes();
+
+        for color in self.pixel_array.iter() {
+            for v in [color.x, color.y, color.z] {
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+
+        return out;
+    }
+}
@@ -1,13 +1,139 @@
-use crate::utils::vector::*;
+use crate::utils::{
+    color_space::{transform, TransferFunction},
+    vector::*,
+};
 
+/// Exponent of the display curve that Unreal's fit and AgX's sigmoid have
+/// baked in; undone to get linear light back.
 const GAMMA: f32 = 2.2;
+/// White point of extended Reinhard when none is given.
+const DEFAULT_WHITE: f32 = 4.0;
 
 // https://64.github.io/tonemapping/
 
-// REINHARD
+/// * Clamp
+/// * Reinhard
+/// * ReinhardExtended { white: f32 }
+/// * Aces
+/// * Unreal
+/// * Hable
+/// * Agx { look: AgxLook }
+/// * PbrNeutral
+///
+/// Curve mapping scene colors to display colors between 0 and 1. Curves take
+/// and return linear Rec.709.
 #[allow(dead_code)]
+#[derive(Copy, Clone)]
+pub enum Tonemapper {
+    /// Cuts off everything above 1.
+    Clamp,
+    Reinhard,
+    /// Reinhard that reaches 1 at `white` instead of at infinity.
+    ReinhardExtended {
+        white: f32,
+    },
+    Aces,
+    Unreal,
+    /// Uncharted 2 filmic curve by John Hable.
+    Hable,
+    /// Troy Sobotka's AgX, which desaturates bright colors towards white
+    /// instead of skewing their hue.
+    Agx {
+        look: AgxLook,
+    },
+    /// Khronos PBR Neutral, which keeps base colors accurate up to a
+    /// brightness of about 0.8.
+    PbrNeutral,
+}
+
+/// * Base
+/// * Golden
+/// * Punchy
+///
+/// Grades AgX's output can get.
+#[allow(dead_code)]
+#[derive(Copy, Clone)]
+pub enum AgxLook {
+    Base,
+    /// Warm and slightly desaturated.
+    Golden,
+    /// More contrast and saturation.
+    Punchy,
+}
+
+impl Tonemapper {
+    /// Tonemapper called `name` on the command line: `clamp`, `reinhard`,
+    /// `reinhard-extended` with an optional white point after a colon (like
+    /// `reinhard-extended:8`), `aces`, `unreal`, `hable`, `agx`,
+    /// `agx-golden`, `agx-punchy` or `pbr-neutral`.
+    pub fn from_name(name: &str) -> Option<Tonemapper> {
+        let tonemapper = match name {
+            "clamp" => Tonemapper::Clamp,
+            "reinhard" => Tonemapper::Reinhard,
+            "reinhard-extended" => Tonemapper::ReinhardExtended {
+                white: DEFAULT_WHITE,
+            },
+            "aces" => Tonemapper::Aces,
+            "unreal" => Tonemapper::Unreal,
+            "hable" => Tonemapper::Hable,
+            "agx" => Tonemapper::Agx {
+                look: AgxLook::Base,
+            },
+            "agx-golden" => Tonemapper::Agx {
+                look: AgxLook::Golden,
+            },
+            "agx-punchy" => Tonemapper::Agx {
+                look: AgxLook::Punchy,
+            },
+            "pbr-neutral" => Tonemapper::PbrNeutral,
+            _ => {
+                let white = name.strip_prefix("reinhard-extended:")?;
+                Tonemapper::ReinhardExtended {
+                    white: white.parse().ok()?,
+                }
+            }
+        };
+        Some(tonemapper)
+    }
+
+    /// Scales `c`, in linear Rec.709, by `exposure` stops, maps it to the
+    /// display and encodes it with the sRGB transfer function.
+    pub fn apply(&self, c: Color, exposure: f32) -> Color {
+        // colors from wider working spaces can be out of gamut
+        let c = map(c, |v| v.max(0.0)) * 2f32.powf(exposure);
+        let display = match *self {
+            Tonemapper::Clamp => clamp(c),
+            Tonemapper::Reinhard => reinhard(c),
+            Tonemapper::ReinhardExtended { white } => reinhard_extended(c, white),
+            Tonemapper::Aces => aces(c),
+            Tonemapper::Unreal => unreal(c),
+            Tonemapper::Hable => hable(c),
+            Tonemapper::Agx { look } => agx(c, look),
+            Tonemapper::PbrNeutral => pbr_neutral(c),
+        };
+        TransferFunction::Srgb.encode_color(map(display, |v| v.clamp(0.0, 1.0)))
+    }
+}
+
+fn map(c: Color, f: impl Fn(f32) -> f32) -> Color {
+    Color::new(f(c.x), f(c.y), f(c.z))
+}
+
+// CLAMP
+
+pub fn clamp(c: Color) -> Color {
+    map(c, |v| v.clamp(0.0, 1.0))
+}
+
+// REINHARD
+
 pub fn reinhard(c: Color) -> Color {
-    (c / (c + 1.0)).pow(1.0 / GAMMA)
+    c / (c + 1.0)
+}
+
+pub fn reinhard_extended(c: Color, white: f32) -> Color {
+    let mapped = map(c, |v| v * (1.0 + v / (white * white)) / (1.0 + v));
+    map(mapped, |v| v.min(1.0))
 }
 
 // FILMIC ACES
@@ -51,8 +177,8 @@ const _ACES_OUTPUT_MATRIX: [Vec3; 3] = [
 fn mul(m: [Vec3; 3], v: &Vec3) -> Vec3 {
     Vec3::new(
         m[0].x * v.x + m[0].y * v.y + m[0].z * v.z,
-        m[1].x * v.y + m[1].y * v.y + m[1].z * v.z,
-        m[2].x * v.y + m[2].y * v.y + m[2].z * v.z,
+        m[1].x * v.x + m[1].y * v.y + m[1].z * v.z,
+        m[2].x * v.x + m[2].y * v.y + m[2].z * v.z,
     )
 }
 
@@ -62,21 +188,198 @@ fn rtt_and_odt_fit(v: Vec3) -> Vec3 {
     return a / b;
 }
 
-#[allow(dead_code)]
+/// Stephen Hill's fit. The input matrix takes linear Rec.709 to ACES AP1,
+/// with the RRT's saturation tweak, and the output matrix takes the result
+/// back.
 pub fn aces(c: Color) -> Color {
     let a = mul(_ACES_INPUT_MATRIX, &c);
     let b = rtt_and_odt_fit(a);
-    mul(_ACES_OUTPUT_MATRIX, &b).pow(1.0 / GAMMA)
+    mul(_ACES_OUTPUT_MATRIX, &b)
 }
 
 // FILMIC UNREAL
-// This one has Gamma Correction baked in.
+// This one has Gamma Correction baked in, which gets undone.
 
-#[allow(dead_code)]
 pub fn unreal(x: Color) -> Color {
     let mut c = x / (x + 0.155) * 1.019;
     c.x = c.x.min(1.0);
     c.y = c.y.min(1.0);
     c.z = c.z.min(1.0);
-    return c;
+    return map(c, |v| TransferFunction::Gamma(GAMMA).decode(v));
+}
+
+// FILMIC HABLE
+// Uncharted 2 curve, with its exposure bias of 2 and linear white of 11.2.
+
+const HABLE_WHITE: f32 = 11.2;
+
+fn hable_partial(x: f32) -> f32 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+pub fn hable(c: Color) -> Color {
+    let white_scale = 1.0 / hable_partial(HABLE_WHITE);
+    map(c, |v| hable_partial(2.0 * v) * white_scale)
+}
+
+// AGX
+// https://iolite-engine.com/blog_posts/minimal_agx_implementation
+
+const AGX_INSET_MATRIX: [Vec3; 3] = [
+    Vec3 {
+        x: 0.84247906,
+        y: 0.0784336,
+        z: 0.079223745,
+    },
+    Vec3 {
+        x: 0.042328242,
+        y: 0.87846864,
+        z: 0.07916613,
+    },
+    Vec3 {
+        x: 0.042375655,
+        y: 0.0784336,
+        z: 0.879143,
+    },
+];
+
+const AGX_OUTSET_MATRIX: [Vec3; 3] = [
+    Vec3 {
+        x: 1.196879,
+        y: -0.09802088,
+        z: -0.09902974,
+    },
+    Vec3 {
+        x: -0.052896852,
+        y: 1.1519031,
+        z: -0.098961177,
+    },
+    Vec3 {
+        x: -0.052971636,
+        y: -0.09804345,
+        z: 1.1510737,
+    },
+];
+
+/// Range of exposures, in stops around middle gray, that AgX maps to the
+/// display.
+const AGX_MIN_EV: f32 = -12.47393;
+const AGX_MAX_EV: f32 = 4.026069;
+
+/// Polynomial fit of AgX's sigmoid over log encoded values.
+fn agx_contrast(x: f32) -> f32 {
+    let (x2, x4) = (x * x, x * x * x * x);
+    15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+        - 0.00232
+}
+
+pub fn agx(c: Color, look: AgxLook) -> Color {
+    let inset = transform(&AGX_INSET_MATRIX, c);
+    let encoded = map(inset, |v| {
+        let ev = v.max(1e-10).log2().clamp(AGX_MIN_EV, AGX_MAX_EV);
+        agx_contrast((ev - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV))
+    });
+
+    // ASC CDL slope and power, then saturation around the original luma
+    let (slope, power, saturation) = match look {
+        AgxLook::Base => (Color::new(1.0, 1.0, 1.0), 1.0, 1.0),
+        AgxLook::Golden => (Color::new(1.0, 0.9, 0.5), 0.8, 0.8),
+        AgxLook::Punchy => (Color::new(1.0, 1.0, 1.0), 1.35, 1.4),
+    };
+    let luma = dot(Vec3::new(0.2126, 0.7152, 0.0722), encoded);
+    let graded = map(encoded * slope, |v| v.max(0.0).powf(power));
+    let graded = map(graded, |v| luma + saturation * (v - luma));
+
+    // the sigmoid produces display values, so after undoing the inset they
+    // get decoded back to linear light
+    let outset = transform(&AGX_OUTSET_MATRIX, graded);
+    map(outset, |v| {
+        TransferFunction::Gamma(GAMMA).decode(v.clamp(0.0, 1.0))
+    })
+}
+
+// KHRONOS PBR NEUTRAL
+// https://github.com/KhronosGroup/ToneMapping/tree/main/PBR_Neutral
+
+const PBR_NEUTRAL_START_COMPRESSION: f32 = 0.8 - 0.04;
+const PBR_NEUTRAL_DESATURATION: f32 = 0.15;
+
+pub fn pbr_neutral(c: Color) -> Color {
+    let x = c.x.min(c.y).min(c.z);
+    let offset = if x < 0.08 { x - 6.25 * x * x } else { 0.04 };
+    let c = c - offset;
+
+    let peak = c.x.max(c.y).max(c.z);
+    if peak < PBR_NEUTRAL_START_COMPRESSION {
+        return map(c, |v| v.max(0.0));
+    }
+
+    let d = 1.0 - PBR_NEUTRAL_START_COMPRESSION;
+    let new_peak = 1.0 - d * d / (peak + d - PBR_NEUTRAL_START_COMPRESSION);
+    let c = c * (new_peak / peak);
+    let g = 1.0 - 1.0 / (PBR_NEUTRAL_DESATURATION * (peak - new_peak) + 1.0);
+    c * (1.0 - g) + Color::new(new_peak, new_peak, new_peak) * g
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAMES: [&str; 10] = [
+        "clamp",
+        "reinhard",
+        "reinhard-extended:8",
+        "aces",
+        "unreal",
+        "hable",
+        "agx",
+        "agx-golden",
+        "agx-punchy",
+        "pbr-neutral",
+    ];
+
+    fn tonemappers() -> impl Iterator<Item = (&'static str, Tonemapper)> {
+        NAMES
+            .iter()
+            .map(|&name| (name, Tonemapper::from_name(name).unwrap()))
+    }
+
+    #[test]
+    fn names_are_parsed() {
+        assert_eq!(tonemappers().count(), NAMES.len());
+        assert!(Tonemapper::from_name("reinhard-extended:bright").is_none());
+        assert!(Tonemapper::from_name("filmic").is_none());
+    }
+
+    #[test]
+    fn black_stays_black() {
+        for (name, tonemapper) in tonemappers() {
+            let c = tonemapper.apply(Color::new(0.0, 0.0, 0.0), 0.0);
+            assert!(c.x.max(c.y).max(c.z) < 1e-3, "{}", name);
+        }
+    }
+
+    #[test]
+    fn grays_brighten_monotonically_within_the_display_range() {
+        for (name, tonemapper) in tonemappers() {
+            let mut previous = 0.0;
+            for stop in -80..80 {
+                let c = tonemapper.apply(Color::new(0.18, 0.18, 0.18), stop as f32 / 10.0);
+                for v in [c.x, c.y, c.z] {
+                    assert!((0.0..=1.0).contains(&v), "{} at {} stops", name, stop);
+                }
+                assert!(c.y >= previous, "{} at {} stops", name, stop);
+                previous = c.y;
+            }
+        }
+    }
+
+    #[test]
+    fn negative_colors_are_clipped() {
+        for (name, tonemapper) in tonemappers() {
+            let c = tonemapper.apply(Color::new(-0.5, 0.2, 0.3), 0.0);
+            assert!(c.x >= 0.0, "{}", name);
+        }
+    }
 }
@@ -0,0 +1,309 @@
+use rayon::prelude::*;
+
+use super::Image;
+use crate::{
+    render::{aov::Aov, film::Film},
+    scenes::Denoise,
+    utils::{color::luminance, color_space::ColorSpace, vector::*},
+};
+
+/// Taps of the B3 spline the à-trous kernel is built from, by distance from
+/// the center.
+const KERNEL: [f32; 3] = [3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+const EPSILON: f32 = 1e-4;
+
+/// What the camera rays of each pixel hit, read from the film's feature
+/// AOVs. Pixels that hit nothing have zero depth.
+struct Guide {
+    albedo: Vec<Color>,
+    normal: Vec<Vec3>,
+    depth: Vec<f32>,
+    /// How fast depth changes per pixel, so sloped surfaces aren't mistaken
+    /// for edges.
+    depth_gradient: Vec<f32>,
+}
+
+/// Removes noise with an edge-avoiding à-trous wavelet filter (Dammertz et
+/// al. 2010): a 5x5 blur applied repeatedly with its taps spread twice as
+/// far each time. Taps only count as far as they match the center in
+/// albedo, normal and depth, and in luminance relative to its noise, which
+/// comes from the per-pixel variance (as in SVGF, Schied et al. 2017).
+///
+/// `image` is the exposed beauty pass of `film`, which has to hold the
+/// albedo, normal and depth AOVs, with colors in `space`.
+pub fn denoise(image: &Image, film: &Film, settings: Denoise, space: ColorSpace) -> Image {
+    let size = image.size;
+    let guide = Guide::new(film, size);
+    let mut color = image.pixels.clone();
+    let mut variance: Vec<f32> = (0..size.1)
+        .flat_map(|y| (0..size.0).map(move |x| film.variance((x, y))))
+        .collect();
+
+    for level in 0..settings.iterations {
+        let step = 1 << level;
+        let noise: Vec<f32> = blur_variance(&variance, size)
+            .iter()
+            .map(|v| v.sqrt())
+            .collect();
+        let luminances: Vec<f32> = color.iter().map(|&c| luminance(c, space)).collect();
+
+        let mut next_color = vec![Color::new(0.0, 0.0, 0.0); color.len()];
+        let mut next_variance = vec![0.0; variance.len()];
+        next_color
+            .par_chunks_mut(size.0 as usize)
+            .zip(next_variance.par_chunks_mut(size.0 as usize))
+            .enumerate()
+            .for_each(|(y, (color_row, variance_row))| {
+                for x in 0..size.0 as usize {
+                    let i = x + y * size.0 as usize;
+                    let luminance_center = luminances[i];
+                    let sigma_luminance = settings.sigma_luminance * noise[i] + EPSILON;
+
+                    let mut sum_color = Color::new(0.0, 0.0, 0.0);
+                    let mut sum_variance = 0.0;
+                    let mut sum_weight = 0.0;
+                    for dy in -2..=2i32 {
+                        for dx in -2..=2i32 {
+                            let qx = x as i32 + dx * step;
+                            let qy = y as i32 + dy * step;
+                            if qx < 0 || qy < 0 || qx >= size.0 as i32 || qy >= size.1 as i32 {
+                                continue;
+                            }
+                            let j = (qx + qy * size.0 as i32) as usize;
+
+                            let kernel = KERNEL[dx.unsigned_abs() as usize]
+                                * KERNEL[dy.unsigned_abs() as usize];
+                            // the center always counts fully, even when its own
+                            // features don't match it (like a zero normal), so
+                            // the weights never sum to zero
+                            let weight = if j == i {
+                                kernel
+                            } else {
+                                let distance = (step as f32) * ((dx * dx + dy * dy) as f32).sqrt();
+                                let luminance_weight = (-(luminances[j] - luminance_center).abs()
+                                    / sigma_luminance)
+                                    .exp();
+                                kernel * luminance_weight * guide.weight(i, j, distance, settings)
+                            };
+
+                            sum_color += weight * color[j];
+                            sum_variance += weight * weight * variance[j];
+                            sum_weight += weight;
+                        }
+                    }
+
+                    color_row[x] = sum_color / sum_weight;
+                    variance_row[x] = sum_variance / (sum_weight * sum_weight);
+                }
+            });
+
+        color = next_color;
+        variance = next_variance;
+    }
+
+    Image {
+        size,
+        pixels: color,
+    }
+}
+
+impl Guide {
+    fn new(film: &Film, size: (u32, u32)) -> Guide {
+        let feature = |aov: Aov| -> Vec<Color> {
+            let index = match film.aov_index(aov) {
+                Some(index) => index,
+                None => panic!("denoising needs the {} AOV", aov.name()),
+            };
+            Image::new(size, |pos| film.aov(index, pos)).pixels
+        };
+
+        let albedo = feature(Aov::Albedo);
+        let normal = feature(Aov::Normal)
+            .into_iter()
+            .map(|n| if n.length() > 0.0 { n.unit() } else { n })
+            .collect();
+        let depth: Vec<f32> = feature(Aov::Depth).iter().map(|d| d.x).collect();
+
+        let (w, h) = (size.0 as usize, size.1 as usize);
+        let mut depth_gradient = vec![0.0; depth.len()];
+        for y in 0..h {
+            for x in 0..w {
+                let i = x + y * w;
+                // the smaller one-sided difference along each axis, so a
+                // neighbouring edge doesn't inflate it
+                let slope = |a: Option<f32>, b: Option<f32>| {
+                    [a, b]
+                        .iter()
+                        .flatten()
+                        .filter(|d| **d > 0.0)
+                        .map(|d| (d - depth[i]).abs())
+                        .reduce(f32::min)
+                        .unwrap_or(0.0)
+                };
+                let dx = slope(
+                    (x > 0).then(|| depth[i - 1]),
+                    (x + 1 < w).then(|| depth[i + 1]),
+                );
+                let dy = slope(
+                    (y > 0).then(|| depth[i - w]),
+                    (y + 1 < h).then(|| depth[i + w]),
+                );
+                depth_gradient[i] = (dx * dx + dy * dy).sqrt();
+            }
+        }
+
+        Guide {
+            albedo,
+            normal,
+            depth,
+            depth_gradient,
+        }
+    }
+
+    /// How much pixel `j`, `distance` pixels away, counts towards denoising
+    /// pixel `i`, going by their features alone.
+    fn weight(&self, i: usize, j: usize, distance: f32, settings: Denoise) -> f32 {
+        let (hit_i, hit_j) = (self.depth[i] > 0.0, self.depth[j] > 0.0);
+        if hit_i != hit_j {
+            return 0.0;
+        }
+        if !hit_i {
+            return 1.0;
+        }
+
+        let normal_weight = dot(self.normal[i], self.normal[j])
+            .max(0.0)
+            .powf(settings.sigma_normal);
+        let depth_weight = (-(self.depth[i] - self.depth[j]).abs()
+            / (settings.sigma_depth * self.depth_gradient[i] * distance + EPSILON))
+            .exp();
+        let albedo_weight = (-(self.albedo[i] - self.albedo[j]).length_squared()
+            / (settings.sigma_albedo * settings.sigma_albedo + EPSILON))
+            .exp();
+        normal_weight * depth_weight * albedo_weight
+    }
+}
+
+/// 3x3 Gaussian blur of the variance, which steadies the luminance weights
+/// against noise in the variance estimate itself.
+fn blur_variance(variance: &[f32], size: (u32, u32)) -> Vec<f32> {
+    let (w, h) = (size.0 as i32, size.1 as i32);
+    let taps = [0.25, 0.5, 0.25];
+    let mut out = vec![0.0; variance.len()];
+    for y in 0..h {
+        for x in 0..w {
+            let (mut sum, mut total) = (0.0, 0.0);
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let (qx, qy) = (x + dx, y + dy);
+                    if qx < 0 || qy < 0 || qx >= w || qy >= h {
+                        continue;
+                    }
+                    let weight = taps[(dx + 1) as usize] * taps[(dy + 1) as usize];
+                    sum += weight * variance[(qx + qy * w) as usize];
+                    total += weight;
+                }
+            }
+            out[(x + y * w) as usize] = sum / total;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::{
+        aov::{FirstHit, PathSample},
+        film::PixelStats,
+        filter::Filter,
+        materials::material::Lobe,
+    };
+
+    const SIZE: (u32, u32) = (16, 12);
+    const SETTINGS: Denoise = Denoise {
+        iterations: 3,
+        sigma_luminance: 4.0,
+        sigma_normal: 128.0,
+        sigma_depth: 1.0,
+        sigma_albedo: 0.1,
+        keep_raw: false,
+    };
+
+    /// A wall whose left half faces the camera and is red and whose right
+    /// half faces sideways and is blue, with 4 samples per pixel whose
+    /// luminance has a standard deviation of about 0.1.
+    fn film() -> Film {
+        let mut film = Film::new(
+            SIZE,
+            Filter::Box { radius: 0.5 },
+            &[Aov::Albedo, Aov::Normal, Aov::Depth],
+        );
+        let mut tile = film.tile((0, 0), SIZE);
+        for y in 0..SIZE.1 {
+            for x in 0..SIZE.0 {
+                let (albedo, normal) = if x < SIZE.0 / 2 {
+                    (Color::new(0.8, 0.1, 0.1), Vec3::new(0.0, 0.0, 1.0))
+                } else {
+                    (Color::new(0.1, 0.1, 0.8), Vec3::new(1.0, 0.0, 0.0))
+                };
+                let sample = PathSample {
+                    first_hit: Some(FirstHit {
+                        dist: 2.0,
+                        normal,
+                        albedo,
+                        p: Point3::new(x as f32, y as f32, -2.0),
+                        material_id: 0,
+                        object_id: 0,
+                        lobe: Lobe::Diffuse,
+                    }),
+                    bounces: 1,
+                    color: Color::new(0.0, 0.0, 0.0),
+                };
+                for _ in 0..4 {
+                    tile.add_sample((x, y), (x as f32 + 0.5, y as f32 + 0.5), &sample);
+                }
+                *tile.stats_mut((x, y)) = PixelStats {
+                    samples: 4,
+                    mean: 0.5,
+                    m2: 0.12,
+                    converged: false,
+                };
+            }
+        }
+        film.merge_tile(&tile);
+        film
+    }
+
+    #[test]
+    fn constant_images_stay_unchanged() {
+        let c = Color::new(0.2, 0.4, 0.6);
+        let image = Image::new(SIZE, |_| c);
+        let denoised = denoise(&image, &film(), SETTINGS, ColorSpace::Rec709);
+        for p in denoised.pixels {
+            assert!((p - c).length() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn noise_is_smoothed_but_edges_are_kept() {
+        let (dark, bright) = (0.1, 1.0);
+        let noise = |(x, y): (u32, u32)| if (x + y) % 2 == 0 { 0.05 } else { -0.05 };
+        let image = Image::new(SIZE, |pos| {
+            let v = if pos.0 < SIZE.0 / 2 { dark } else { bright } + noise(pos);
+            Color::new(v, v, v)
+        });
+        let denoised = denoise(&image, &film(), SETTINGS, ColorSpace::Rec709);
+
+        for y in 1..SIZE.1 - 1 {
+            // right at the edge, where a plain blur would mix both sides
+            let left = denoised.get((SIZE.0 / 2 - 1, y)).y;
+            let right = denoised.get((SIZE.0 / 2, y)).y;
+            assert!((left - dark).abs() < 0.02, "{}", left);
+            assert!((right - bright).abs() < 0.02, "{}", right);
+            // and away from it, the checkerboard is gone
+            assert!((denoised.get((3, y)).y - dark).abs() < 0.02);
+            assert!((denoised.get((SIZE.0 - 4, y)).y - bright).abs() < 0.02);
+        }
+    }
+}
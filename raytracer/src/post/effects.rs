@@ -0,0 +1,347 @@
+use rayon::prelude::*;
+
+use super::{glare::glare, Image};
+use crate::{
+    render::{
+        camera::Aperture,
+        sample_generator::{hash, hash_combine},
+    },
+    utils::{color::luminance, color_space::ColorSpace, vector::*},
+};
+
+/// Smallest glare kernel, in pixels across. Smaller ones miss the center of
+/// the diffraction pattern and add up to zero.
+const MIN_GLARE_SIZE: u32 = 3;
+
+/// * Bloom { threshold: f32, intensity: f32, radius: f32, levels: u32 }
+/// * Glare { threshold: f32, intensity: f32, size: u32 }
+/// * Vignette { strength: f32 }
+/// * Grain { amount: f32, size: f32 }
+///
+/// Lens and film effect applied to the linear image before tonemapping.
+/// Thresholds are luminances; only light above them blooms or glares.
+#[allow(dead_code)]
+#[derive(Copy, Clone)]
+pub enum Effect {
+    /// Glow around highlights from light scattered in the lens: the bright
+    /// parts blurred at `levels` scales, each twice as wide as the last,
+    /// starting from a standard deviation of `radius` pixels. The average
+    /// gets added back scaled by `intensity`. `radius` has to be positive
+    /// and `levels` at least 1.
+    Bloom {
+        threshold: f32,
+        intensity: f32,
+        radius: f32,
+        levels: u32,
+    },
+    /// Star shaped streaks from light diffracted by the camera's aperture,
+    /// spreading up to `size` / 2 pixels from each highlight. `size` has to
+    /// be at least 3.
+    Glare {
+        threshold: f32,
+        intensity: f32,
+        size: u32,
+    },
+    /// Darkens towards the corners like the cos⁴ falloff of a lens; 0
+    /// changes nothing and the corners get 1 / (1 + strength)² as bright.
+    Vignette { strength: f32 },
+    /// Multiplicative noise with clumps about `size` pixels across,
+    /// varying brightness by up to `amount`.
+    Grain { amount: f32, size: f32 },
+}
+
+/// Panics on settings the effects can't work with, so a render fails before
+/// it starts rather than after.
+pub fn check(effects: &[Effect]) {
+    let non_negative = |name: &str, value: f32| {
+        if value < 0.0 {
+            panic!("{} can't be negative, not {}", name, value);
+        }
+    };
+    for effect in effects {
+        match *effect {
+            Effect::Bloom {
+                intensity,
+                radius,
+                levels,
+                ..
+            } => {
+                non_negative("bloom intensity", intensity);
+                if radius <= 0.0 {
+                    panic!("bloom radius has to be positive, not {}", radius);
+                }
+                if levels == 0 {
+                    panic!("bloom needs at least 1 level");
+                }
+            }
+            Effect::Glare {
+                intensity, size, ..
+            } => {
+                non_negative("glare intensity", intensity);
+                if size < MIN_GLARE_SIZE {
+                    panic!(
+                        "glare size has to be at least {}, not {}",
+                        MIN_GLARE_SIZE, size
+                    );
+                }
+            }
+            Effect::Vignette { strength } => non_negative("vignette strength", strength),
+            Effect::Grain { amount, .. } => non_negative("grain amount", amount),
+        }
+    }
+}
+
+/// Applies the effects in order to an image with colors in `space`. Glare
+/// takes its shape from `aperture` and grain is seeded by `seed`.
+pub fn apply(
+    image: &Image,
+    effects: &[Effect],
+    space: ColorSpace,
+    aperture: &Aperture,
+    seed: u32,
+) -> Image {
+    let mut image = Image {
+        size: image.size,
+        pixels: image.pixels.clone(),
+    };
+    for effect in effects {
+        image = match *effect {
+            Effect::Bloom {
+                threshold,
+                intensity,
+                radius,
+                levels,
+            } => add(
+                &image,
+                &bloom(&image, threshold, radius, levels, space),
+                intensity,
+            ),
+            Effect::Glare {
+                threshold,
+                intensity,
+                size,
+            } => add(
+                &image,
+                &glare(&image, threshold, size, aperture, space),
+                intensity,
+            ),
+            Effect::Vignette { strength } => vignette(&image, strength),
+            Effect::Grain { amount, size } => grain(&image, amount, size, seed),
+        };
+    }
+    image
+}
+
+/// The part of `c`, in `space`, above `threshold` in luminance, keeping its
+/// hue.
+pub fn bright_pass(c: Color, threshold: f32, space: ColorSpace) -> Color {
+    let l = luminance(c, space);
+    if l <= threshold {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+    c * ((l - threshold) / l)
+}
+
+fn add(image: &Image, light: &Image, intensity: f32) -> Image {
+    Image::new(image.size, |pos| {
+        image.get(pos) + intensity * light.get(pos)
+    })
+}
+
+/// The bright parts of `image` blurred at every level of a pyramid that
+/// halves the resolution each level, averaged at full resolution.
+fn bloom(image: &Image, threshold: f32, radius: f32, levels: u32, space: ColorSpace) -> Image {
+    let mut level = Image::new(image.size, |pos| {
+        bright_pass(image.get(pos), threshold, space)
+    });
+    let mut blurred = Vec::new();
+    for l in 0..levels {
+        if l > 0 {
+            level = downsample(&level);
+        }
+        blurred.push(gaussian_blur(&level, radius));
+    }
+
+    Image::new(image.size, |(x, y)| {
+        let sum = blurred
+            .iter()
+            .enumerate()
+            .fold(Color::new(0.0, 0.0, 0.0), |sum, (l, b)| {
+                let scale = (1 << l) as f32;
+                let u = (x as f32 + 0.5) / scale - 0.5;
+                let v = (y as f32 + 0.5) / scale - 0.5;
+                sum + bilinear(b, u, v)
+            });
+        sum / levels.max(1) as f32
+    })
+}
+
+/// Averages 2x2 blocks of pixels.
+fn downsample(image: &Image) -> Image {
+    let size = ((image.size.0 / 2).max(1), (image.size.1 / 2).max(1));
+    Image::new(size, |(x, y)| {
+        let at = |dx: u32, dy: u32| {
+            image.get((
+                (2 * x + dx).min(image.size.0 - 1),
+                (2 * y + dy).min(image.size.1 - 1),
+            ))
+        };
+        (at(0, 0) + at(1, 0) + at(0, 1) + at(1, 1)) / 4.0
+    })
+}
+
+fn bilinear(image: &Image, u: f32, v: f32) -> Color {
+    let (max_x, max_y) = ((image.size.0 - 1) as f32, (image.size.1 - 1) as f32);
+    let (u, v) = (u.clamp(0.0, max_x), v.clamp(0.0, max_y));
+    let (x0, y0) = (u as u32, v as u32);
+    let (x1, y1) = (
+        (x0 + 1).min(image.size.0 - 1),
+        (y0 + 1).min(image.size.1 - 1),
+    );
+    let (fx, fy) = (u - x0 as f32, v - y0 as f32);
+    let bottom = image.get((x0, y0)) * (1.0 - fx) + image.get((x1, y0)) * fx;
+    let top = image.get((x0, y1)) * (1.0 - fx) + image.get((x1, y1)) * fx;
+    bottom * (1.0 - fy) + top * fy
+}
+
+/// Separable Gaussian blur with standard deviation `sigma`, clamping at the
+/// edges.
+fn gaussian_blur(image: &Image, sigma: f32) -> Image {
+    let reach = (3.0 * sigma).ceil().max(1.0) as i32;
+    let weights: Vec<f32> = (-reach..=reach)
+        .map(|d| (-(d * d) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = weights.iter().sum();
+    let weights: Vec<f32> = weights.iter().map(|w| w / total).collect();
+
+    let (width, height) = (image.size.0 as i32, image.size.1 as i32);
+    let pass = |pixels: &[Color], horizontal: bool| -> Vec<Color> {
+        let mut out = vec![Color::new(0.0, 0.0, 0.0); pixels.len()];
+        out.par_chunks_mut(width as usize)
+            .enumerate()
+            .for_each(|(y, row)| {
+                for (x, out) in row.iter_mut().enumerate() {
+                    let (x, y) = (x as i32, y as i32);
+                    for (w, d) in weights.iter().zip(-reach..=reach) {
+                        let (sx, sy) = if horizontal {
+                            ((x + d).clamp(0, width - 1), y)
+                        } else {
+                            (x, (y + d).clamp(0, height - 1))
+                        };
+                        *out += *w * pixels[(sx + sy * width) as usize];
+                    }
+                }
+            });
+        out
+    };
+    let pixels = pass(&pass(&image.pixels, true), false);
+    Image {
+        size: image.size,
+        pixels,
+    }
+}
+
+fn vignette(image: &Image, strength: f32) -> Image {
+    let (w, h) = (image.size.0 as f32, image.size.1 as f32);
+    // squared distance from the center, 1 in the corners
+    let corner = (w * w + h * h) / 4.0;
+    Image::new(image.size, |(x, y)| {
+        let (dx, dy) = (x as f32 + 0.5 - w / 2.0, y as f32 + 0.5 - h / 2.0);
+        let r2 = (dx * dx + dy * dy) / corner;
+        let falloff = 1.0 + strength * r2;
+        image.get((x, y)) / (falloff * falloff)
+    })
+}
+
+/// Value noise: random values on a grid of `size` pixel cells, blended
+/// smoothly in between.
+fn grain(image: &Image, amount: f32, size: f32, seed: u32) -> Image {
+    let size = size.max(1.0);
+    let value = |x: i32, y: i32| {
+        let h = hash(hash_combine(hash_combine(seed, x as u32), y as u32));
+        (h >> 8) as f32 / (1u32 << 23) as f32 - 1.0
+    };
+    let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+    Image::new(image.size, |(x, y)| {
+        let (u, v) = (x as f32 / size, y as f32 / size);
+        let (x0, y0) = (u.floor() as i32, v.floor() as i32);
+        let (fx, fy) = (smooth(u - x0 as f32), smooth(v - y0 as f32));
+        let bottom = value(x0, y0) + fx * (value(x0 + 1, y0) - value(x0, y0));
+        let top = value(x0, y0 + 1) + fx * (value(x0 + 1, y0 + 1) - value(x0, y0 + 1));
+        let noise = bottom + fy * (top - bottom);
+        image.get((x, y)) * (1.0 + amount * noise).max(0.0)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bloom_effect(intensity: f32, radius: f32, levels: u32) -> Effect {
+        Effect::Bloom {
+            threshold: 1.0,
+            intensity,
+            radius,
+            levels,
+        }
+    }
+
+    #[test]
+    fn sensible_effects_pass() {
+        check(&[
+            bloom_effect(0.5, 2.0, 3),
+            Effect::Glare {
+                threshold: 1.0,
+                intensity: 0.1,
+                size: 3,
+            },
+            Effect::Vignette { strength: 0.0 },
+            Effect::Grain {
+                amount: 0.1,
+                size: 1.0,
+            },
+        ]);
+    }
+
+    #[test]
+    #[should_panic(expected = "bloom radius")]
+    fn bloom_needs_a_radius() {
+        check(&[bloom_effect(0.5, 0.0, 3)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least 1 level")]
+    fn bloom_needs_a_level() {
+        check(&[bloom_effect(0.5, 2.0, 0)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "bloom intensity")]
+    fn bloom_intensity_is_not_negative() {
+        check(&[bloom_effect(-0.5, 2.0, 3)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "vignette strength")]
+    fn vignette_strength_is_not_negative() {
+        check(&[Effect::Vignette { strength: -1.0 }]);
+    }
+
+    #[test]
+    fn bloom_keeps_the_energy_of_a_highlight() {
+        let size = (64, 48);
+        let image = Image::new(size, |pos| {
+            let v = if pos == (32, 24) { 10.0 } else { 0.0 };
+            Color::new(v, v, v)
+        });
+        let bloom = bloom(&image, 1.0, 1.5, 3, ColorSpace::Rec709);
+        let sum = bloom
+            .pixels
+            .iter()
+            .fold(Color::new(0.0, 0.0, 0.0), |sum, c| sum + *c);
+        for v in [sum.x, sum.y, sum.z] {
+            assert!((v - 9.0).abs() < 0.05, "{}", v);
+        }
+        assert!(bloom.get((32, 24)).y > bloom.get((36, 24)).y);
+    }
+}
@@ -0,0 +1,179 @@
+use rayon::prelude::*;
+use std::ops::{Add, Mul, Sub};
+
+const PI: f32 = std::f32::consts::PI;
+
+#[derive(Copy, Clone)]
+pub struct Complex {
+    pub re: f32,
+    pub im: f32,
+}
+
+impl Complex {
+    pub fn new(re: f32, im: f32) -> Complex {
+        Complex { re, im }
+    }
+
+    pub fn norm_squared(self) -> f32 {
+        self.re * self.re + self.im * self.im
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+/// In-place radix-2 Cooley-Tukey transform. The length has to be a power
+/// of two; the inverse is scaled by 1 / length so it undoes the forward one.
+pub fn fft(data: &mut [Complex], inverse: bool) {
+    let n = data.len();
+    debug_assert!(n.is_power_of_two());
+
+    // bit-reversal permutation, so the butterflies can work in place
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut len = 2;
+    while len <= n {
+        let angle = sign * 2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                // computing each twiddle directly keeps the error from
+                // piling up along large transforms
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let twiddle = Complex::new(cos, sin);
+                let even = data[start + k];
+                let odd = twiddle * data[start + k + len / 2];
+                data[start + k] = even + odd;
+                data[start + k + len / 2] = even - odd;
+            }
+        }
+        len <<= 1;
+    }
+
+    if inverse {
+        let scale = 1.0 / n as f32;
+        for v in data.iter_mut() {
+            *v = Complex::new(v.re * scale, v.im * scale);
+        }
+    }
+}
+
+/// Transforms a row-major grid of `size.0` by `size.1` values, both powers
+/// of two: every row, then every column.
+pub fn fft_2d(data: &mut [Complex], size: (usize, usize), inverse: bool) {
+    let (width, height) = size;
+    data.par_chunks_mut(width).for_each(|row| fft(row, inverse));
+
+    let mut columns = transpose(data, size);
+    columns
+        .par_chunks_mut(height)
+        .for_each(|column| fft(column, inverse));
+    data.copy_from_slice(&transpose(&columns, (height, width)));
+}
+
+fn transpose(data: &[Complex], (width, height): (usize, usize)) -> Vec<Complex> {
+    let mut transposed = Vec::with_capacity(data.len());
+    for x in 0..width {
+        for y in 0..height {
+            transposed.push(data[x + y * width]);
+        }
+    }
+    transposed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Arbitrary but repeatable values.
+    fn signal(len: usize) -> Vec<Complex> {
+        (0..len)
+            .map(|i| Complex::new((i as f32 * 0.7).sin() + 0.3, (i as f32 * 1.3).cos()))
+            .collect()
+    }
+
+    fn assert_close(a: &[Complex], b: &[Complex]) {
+        for (a, b) in a.iter().zip(b.iter()) {
+            assert!((*a - *b).norm_squared() < 1e-10);
+        }
+    }
+
+    #[test]
+    fn inverse_undoes_forward() {
+        let original = signal(256);
+        let mut data = original.clone();
+        fft(&mut data, false);
+        fft(&mut data, true);
+        assert_close(&data, &original);
+    }
+
+    #[test]
+    fn inverse_undoes_forward_in_2d() {
+        let original = signal(32 * 8);
+        let mut data = original.clone();
+        fft_2d(&mut data, (32, 8), false);
+        fft_2d(&mut data, (32, 8), true);
+        assert_close(&data, &original);
+    }
+
+    #[test]
+    fn impulse_has_a_flat_spectrum() {
+        let mut data = vec![Complex::new(0.0, 0.0); 16 * 4];
+        data[0] = Complex::new(1.0, 0.0);
+        fft_2d(&mut data, (16, 4), false);
+        assert_close(&data, &[Complex::new(1.0, 0.0); 16 * 4]);
+    }
+
+    #[test]
+    fn sinusoid_lands_in_its_frequency() {
+        let n = 64;
+        let mut data: Vec<Complex> = (0..n)
+            .map(|i| Complex::new((2.0 * PI * 5.0 * i as f32 / n as f32).cos(), 0.0))
+            .collect();
+        fft(&mut data, false);
+        for (k, v) in data.iter().enumerate() {
+            let expected = if k == 5 || k == n - 5 {
+                n as f32 / 2.0
+            } else {
+                0.0
+            };
+            assert!(
+                (v.norm_squared().sqrt() - expected).abs() < 1e-3,
+                "bin {}",
+                k
+            );
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use super::{
+    effects::bright_pass,
+    fft::{fft_2d, Complex},
+    Image,
+};
+use crate::{
+    render::camera::Aperture,
+    utils::{color_space::ColorSpace, vector::*},
+};
+
+/// Wavelengths in nanometers that stand in for the red, green and blue
+/// channels; diffraction spreads longer ones further.
+const WAVELENGTHS: [f32; 3] = [610.0, 550.0, 465.0];
+/// Diameter of the lens opening as a fraction of the grid it is diffracted
+/// in, which sets the size of the pattern's core to a few pixels.
+const PUPIL_FRACTION: f32 = 0.25;
+/// Subsamples per axis when rasterizing the aperture, to smooth its edges.
+const PUPIL_SUBSAMPLES: u32 = 4;
+
+/// Light diffracted around the edges of the aperture: the parts of `image`
+/// brighter than `threshold` convolved with the aperture's far-field
+/// diffraction pattern, `size` pixels across. Straight blades give the
+/// streaks of a star, a circular opening gives rings.
+pub fn glare(
+    image: &Image,
+    threshold: f32,
+    size: u32,
+    aperture: &Aperture,
+    space: ColorSpace,
+) -> Image {
+    let n = size.next_power_of_two() as usize;
+    let psf = point_spread(aperture, n);
+
+    let (width, height) = (image.size.0 as usize, image.size.1 as usize);
+    // padded so light spread past one edge doesn't wrap around onto the other
+    let padded = (
+        (width + n).next_power_of_two(),
+        (height + n).next_power_of_two(),
+    );
+
+    let bright: Vec<Color> = image
+        .pixels
+        .iter()
+        .map(|&c| bright_pass(c, threshold, space))
+        .collect();
+    let channels: Vec<Vec<f32>> = (0..3)
+        .map(|channel| {
+            let kernel = kernel(&psf, n, WAVELENGTHS[1] / WAVELENGTHS[channel]);
+            let mut kernel_spectrum = vec![Complex::new(0.0, 0.0); padded.0 * padded.1];
+            for y in 0..n {
+                for x in 0..n {
+                    // centered on the origin, wrapping around
+                    let px = (x + padded.0 - n / 2) % padded.0;
+                    let py = (y + padded.1 - n / 2) % padded.1;
+                    kernel_spectrum[px + py * padded.0] = Complex::new(kernel[x + y * n], 0.0);
+                }
+            }
+            fft_2d(&mut kernel_spectrum, padded, false);
+
+            let mut spectrum = vec![Complex::new(0.0, 0.0); padded.0 * padded.1];
+            for y in 0..height {
+                for x in 0..width {
+                    let value = channel_of(bright[x + y * width], channel);
+                    spectrum[x + y * padded.0] = Complex::new(value, 0.0);
+                }
+            }
+            fft_2d(&mut spectrum, padded, false);
+            for (s, k) in spectrum.iter_mut().zip(kernel_spectrum.iter()) {
+                *s = *s * *k;
+            }
+            fft_2d(&mut spectrum, padded, true);
+
+            (0..height)
+                .flat_map(|y| (0..width).map(move |x| (x, y)))
+                .map(|(x, y)| spectrum[x + y * padded.0].re.max(0.0))
+                .collect()
+        })
+        .collect();
+
+    Image::new(image.size, |(x, y)| {
+        let i = x as usize + y as usize * width;
+        Color::new(channels[0][i], channels[1][i], channels[2][i])
+    })
+}
+
+fn channel_of(c: Color, channel: usize) -> f32 {
+    match channel {
+        0 => c.x,
+        1 => c.y,
+        _ => c.z,
+    }
+}
+
+/// Fraunhofer diffraction pattern of the aperture on an `n` by `n` grid,
+/// centered: the squared magnitude of the Fourier transform of its opening.
+fn point_spread(aperture: &Aperture, n: usize) -> Vec<f32> {
+    let radius = PUPIL_FRACTION * n as f32 / 2.0;
+    let mut pupil = vec![Complex::new(0.0, 0.0); n * n];
+    for y in 0..n {
+        for x in 0..n {
+            let mut transmittance = 0.0;
+            for s in 0..PUPIL_SUBSAMPLES * PUPIL_SUBSAMPLES {
+                let sx = (s % PUPIL_SUBSAMPLES) as f32 + 0.5;
+                let sy = (s / PUPIL_SUBSAMPLES) as f32 + 0.5;
+                let u = (x as f32 + sx / PUPIL_SUBSAMPLES as f32 - n as f32 / 2.0) / radius;
+                let v = (y as f32 + sy / PUPIL_SUBSAMPLES as f32 - n as f32 / 2.0) / radius;
+                transmittance += aperture.transmittance(u, v);
+            }
+            let transmittance = transmittance / (PUPIL_SUBSAMPLES * PUPIL_SUBSAMPLES) as f32;
+            pupil[x + y * n] = Complex::new(transmittance, 0.0);
+        }
+    }
+    fft_2d(&mut pupil, (n, n), false);
+
+    // move the zero frequency from the corner to the center
+    let mut psf = vec![0.0; n * n];
+    for y in 0..n {
+        for x in 0..n {
+            psf[(x + n / 2) % n + (y + n / 2) % n * n] = pupil[x + y * n].norm_squared();
+        }
+    }
+    psf
+}
+
+/// The pattern for one channel, stretched by `scale` around its center,
+/// faded out towards the edges of the grid and normalized to sum to 1.
+fn kernel(psf: &[f32], n: usize, scale: f32) -> Vec<f32> {
+    let center = (n / 2) as f32;
+    let sample = |x: f32, y: f32| {
+        if x < 0.0 || y < 0.0 || x >= (n - 1) as f32 || y >= (n - 1) as f32 {
+            return 0.0;
+        }
+        let (x0, y0) = (x as usize, y as usize);
+        let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+        let at = |dx, dy| psf[(x0 + dx) + (y0 + dy) * n];
+        let bottom = at(0, 0) + fx * (at(1, 0) - at(0, 0));
+        let top = at(0, 1) + fx * (at(1, 1) - at(0, 1));
+        bottom + fy * (top - bottom)
+    };
+
+    let mut kernel = vec![0.0; n * n];
+    for y in 0..n {
+        for x in 0..n {
+            let (dx, dy) = (x as f32 - center, y as f32 - center);
+            let r = (dx * dx + dy * dy).sqrt() / center;
+            if r < 1.0 {
+                let window = (1.0 - r * r) * (1.0 - r * r);
+                kernel[x + y * n] = window * sample(center + dx * scale, center + dy * scale);
+            }
+        }
+    }
+    let sum: f32 = kernel.iter().sum();
+    kernel.iter().map(|v| v / sum).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A black image with one pixel `value` bright.
+    fn single_highlight(size: (u32, u32), pos: (u32, u32), value: f32) -> Image {
+        Image::new(size, |p| {
+            let v = if p == pos { value } else { 0.0 };
+            Color::new(v, v, v)
+        })
+    }
+
+    fn total(image: &Image) -> Color {
+        image
+            .pixels
+            .iter()
+            .fold(Color::new(0.0, 0.0, 0.0), |sum, c| sum + *c)
+    }
+
+    fn image_is_black(image: &Image) -> bool {
+        image
+            .pixels
+            .iter()
+            .all(|c| c.x == 0.0 && c.y == 0.0 && c.z == 0.0)
+    }
+
+    #[test]
+    fn glare_keeps_the_energy_of_a_highlight() {
+        let image = single_highlight((48, 40), (24, 20), 10.0);
+        let apertures = [
+            Aperture::Circular,
+            Aperture::Polygonal {
+                blades: 6,
+                rotation: 0.0,
+            },
+        ];
+        for aperture in apertures.iter() {
+            let glare = glare(&image, 1.0, 15, aperture, ColorSpace::Rec709);
+            let sum = total(&glare);
+            for v in [sum.x, sum.y, sum.z] {
+                assert!((v - 9.0).abs() < 0.05, "{}", v);
+            }
+            // spread out, but centered on the highlight
+            let center = glare.get((24, 20));
+            assert!(center.y > 0.0 && center.y < 9.0);
+            assert!(center.y > glare.get((30, 20)).y);
+        }
+    }
+
+    #[test]
+    fn light_below_the_threshold_does_not_glare() {
+        let image = single_highlight((16, 16), (8, 8), 0.5);
+        let glare = glare(&image, 1.0, 7, &Aperture::Circular, ColorSpace::Rec709);
+        assert!(image_is_black(&glare));
+    }
+}
@@ -0,0 +1,77 @@
+use super::lut::{Interpolation, Lut};
+use crate::utils::{color::luminance, color_space::ColorSpace, vector::*};
+
+/// * Lut { file_path: &'static str, interpolation: Interpolation }
+/// * LiftGammaGain { lift: Color, gamma: Color, gain: Color }
+/// * Saturation(f32)
+/// * Contrast { contrast: f32, pivot: f32 }
+///
+/// One step of a color grade. Grades work on display values between 0 and
+/// 1, after tonemapping.
+#[allow(dead_code)]
+#[derive(Copy, Clone)]
+pub enum GradeStep {
+    /// Looks colors up in a .cube file.
+    Lut {
+        file_path: &'static str,
+        interpolation: Interpolation,
+    },
+    /// Per channel: `lift` raises the blacks, `gain` scales the whites and
+    /// `gamma` bends the midtones, brightening them above 1. Zero lift and
+    /// unit gamma and gain change nothing.
+    LiftGammaGain {
+        lift: Color,
+        gamma: Color,
+        gain: Color,
+    },
+    /// Scales the distance of colors from gray; 0 is grayscale, 1 unchanged.
+    Saturation(f32),
+    /// Scales the distance of values from `pivot`.
+    Contrast { contrast: f32, pivot: f32 },
+}
+
+/// Color grade ready to apply, with its LUTs loaded.
+pub struct Grade {
+    steps: &'static [GradeStep],
+    /// Table of every step that is a LUT, by step.
+    luts: Vec<Option<Lut>>,
+}
+
+impl Grade {
+    pub fn new(steps: &'static [GradeStep]) -> Grade {
+        let luts = steps
+            .iter()
+            .map(|step| match step {
+                GradeStep::Lut { file_path, .. } => Some(Lut::from_file(file_path)),
+                _ => None,
+            })
+            .collect();
+        Grade { steps, luts }
+    }
+
+    /// Applies the steps in order.
+    pub fn apply(&self, c: Color) -> Color {
+        let mut c = c;
+        for (step, lut) in self.steps.iter().zip(self.luts.iter()) {
+            c = match (*step, lut) {
+                (GradeStep::Lut { interpolation, .. }, Some(lut)) => lut.apply(c, interpolation),
+                (GradeStep::LiftGammaGain { lift, gamma, gain }, _) => {
+                    let lifted = c * (gain - lift) + lift;
+                    Color::new(
+                        lifted.x.max(0.0).powf(1.0 / gamma.x),
+                        lifted.y.max(0.0).powf(1.0 / gamma.y),
+                        lifted.z.max(0.0).powf(1.0 / gamma.z),
+                    )
+                }
+                (GradeStep::Saturation(saturation), _) => {
+                    // display colors have Rec.709 primaries
+                    let gray = luminance(c, ColorSpace::Rec709);
+                    Color::new(gray, gray, gray) + saturation * (c - gray)
+                }
+                (GradeStep::Contrast { contrast, pivot }, _) => (c - pivot) * contrast + pivot,
+                (GradeStep::Lut { .. }, None) => unreachable!(),
+            };
+        }
+        c
+    }
+}
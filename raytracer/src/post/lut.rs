@@ -0,0 +1,336 @@
+use std::fs;
+
+use crate::utils::vector::*;
+
+/// * Trilinear
+/// * Tetrahedral
+///
+/// How a 3D LUT blends the entries around a color.
+#[allow(dead_code)]
+#[derive(Copy, Clone)]
+pub enum Interpolation {
+    /// Blends the 8 corners of the cell the color falls in.
+    Trilinear,
+    /// Blends the 4 corners of the tetrahedron within the cell that the
+    /// color falls in. Cheaper, and keeps the gray axis exactly neutral.
+    Tetrahedral,
+}
+
+/// Entries of a 1D or 3D table along with the input range they cover.
+struct Table {
+    /// Entries per axis.
+    size: usize,
+    /// `size` entries for 1D tables, `size`³ with red changing fastest for
+    /// 3D ones.
+    entries: Vec<Color>,
+    domain_min: Color,
+    domain_max: Color,
+}
+
+impl Table {
+    /// Position of `c` within the domain, scaled to entry indices.
+    fn position(&self, c: Color) -> Color {
+        let scale = (self.size - 1) as f32;
+        let t = (c - self.domain_min) / (self.domain_max - self.domain_min);
+        Color::new(
+            t.x.clamp(0.0, 1.0) * scale,
+            t.y.clamp(0.0, 1.0) * scale,
+            t.z.clamp(0.0, 1.0) * scale,
+        )
+    }
+}
+
+/// Color lookup table from an Adobe or Resolve .cube file: a 1D table per
+/// channel, a 3D table, or a 1D shaper followed by a 3D table.
+pub struct Lut {
+    shaper: Option<Table>,
+    cube: Option<Table>,
+}
+
+impl Lut {
+    /// Loads a .cube file. Besides the Adobe keywords (`LUT_1D_SIZE`,
+    /// `LUT_3D_SIZE`, `DOMAIN_MIN` and `DOMAIN_MAX`) this understands
+    /// Resolve's `LUT_1D_INPUT_RANGE` and `LUT_3D_INPUT_RANGE`, with which a
+    /// file can hold a 1D shaper before its 3D table. Other keywords are
+    /// skipped.
+    pub fn from_file(file_path: &str) -> Lut {
+        let text = match fs::read_to_string(file_path) {
+            Err(why) => panic!("couldn't read {}: {}", file_path, why),
+            Ok(text) => text,
+        };
+
+        let parse = |v: &str| -> f32 {
+            match v.parse() {
+                Err(why) => panic!("bad value {:?} in {}: {}", v, file_path, why),
+                Ok(v) => v,
+            }
+        };
+        let parse_size = |values: &[&str]| -> usize {
+            if values.len() != 1 {
+                panic!("expected 1 value in {}: {:?}", file_path, values);
+            }
+            parse(values[0]) as usize
+        };
+        let parse_color = |values: &[&str]| -> Color {
+            if values.len() != 3 {
+                panic!("expected 3 values in {}: {:?}", file_path, values);
+            }
+            Color::new(parse(values[0]), parse(values[1]), parse(values[2]))
+        };
+        let parse_range = |values: &[&str]| -> (Color, Color) {
+            if values.len() != 2 {
+                panic!("expected 2 values in {}: {:?}", file_path, values);
+            }
+            let (min, max) = (parse(values[0]), parse(values[1]));
+            (Color::new(min, min, min), Color::new(max, max, max))
+        };
+
+        let (mut size_1d, mut size_3d) = (0, 0);
+        let mut domain = (Color::new(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0));
+        let (mut range_1d, mut range_3d) = (None, None);
+        let mut entries = Vec::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let (keyword, values) = (words[0], &words[1..]);
+            // entries are the only lines that start with a number
+            if keyword.starts_with(|c: char| c.is_ascii_digit() || "+-.".contains(c)) {
+                entries.push(parse_color(&words));
+                continue;
+            }
+            match keyword {
+                "LUT_1D_SIZE" => size_1d = parse_size(values),
+                "LUT_3D_SIZE" => size_3d = parse_size(values),
+                "DOMAIN_MIN" => domain.0 = parse_color(values),
+                "DOMAIN_MAX" => domain.1 = parse_color(values),
+                "LUT_1D_INPUT_RANGE" => range_1d = Some(parse_range(values)),
+                "LUT_3D_INPUT_RANGE" => range_3d = Some(parse_range(values)),
+                // TITLE and keywords of other tools, like LUT_IN_VIDEO_RANGE
+                _ => {}
+            }
+        }
+
+        if size_1d == 1 || size_3d == 1 {
+            panic!("LUT sizes in {} have to be at least 2", file_path);
+        }
+        if entries.len() != size_1d + size_3d.pow(3) {
+            panic!(
+                "expected {} entries in {} but there are {}",
+                size_1d + size_3d.pow(3),
+                file_path,
+                entries.len()
+            );
+        }
+
+        let cube_entries = entries.split_off(size_1d);
+        let table = |size, entries, range: Option<(Color, Color)>| {
+            let (domain_min, domain_max) = range.unwrap_or(domain);
+            Table {
+                size,
+                entries,
+                domain_min,
+                domain_max,
+            }
+        };
+        Lut {
+            shaper: Some(table(size_1d, entries, range_1d)).filter(|t| t.size > 0),
+            cube: Some(table(size_3d, cube_entries, range_3d)).filter(|t| t.size > 0),
+        }
+    }
+
+    pub fn apply(&self, c: Color, interpolation: Interpolation) -> Color {
+        let mut c = c;
+        if let Some(shaper) = &self.shaper {
+            c = apply_1d(shaper, c);
+        }
+        if let Some(cube) = &self.cube {
+            c = match interpolation {
+                Interpolation::Trilinear => trilinear(cube, c),
+                Interpolation::Tetrahedral => tetrahedral(cube, c),
+            };
+        }
+        c
+    }
+}
+
+/// Looks every channel up separately, interpolating linearly.
+fn apply_1d(table: &Table, c: Color) -> Color {
+    let p = table.position(c);
+    let lookup = |t: f32, channel: fn(&Color) -> f32| {
+        let i = (t as usize).min(table.size - 2);
+        let f = t - i as f32;
+        let (a, b) = (channel(&table.entries[i]), channel(&table.entries[i + 1]));
+        a + f * (b - a)
+    };
+    Color::new(
+        lookup(p.x, |c| c.x),
+        lookup(p.y, |c| c.y),
+        lookup(p.z, |c| c.z),
+    )
+}
+
+/// The cell containing `c`: a function returning the entry at a corner, by
+/// its offsets along red, green and blue, and the position of `c` within it.
+fn cell(table: &Table, c: Color) -> (impl Fn(usize, usize, usize) -> Color + '_, Color) {
+    let p = table.position(c);
+    let n = table.size;
+    let (r, g, b) = (
+        (p.x as usize).min(n - 2),
+        (p.y as usize).min(n - 2),
+        (p.z as usize).min(n - 2),
+    );
+    let fraction = Color::new(p.x - r as f32, p.y - g as f32, p.z - b as f32);
+    let corner = move |dr, dg, db| table.entries[(r + dr) + (g + dg) * n + (b + db) * n * n];
+    (corner, fraction)
+}
+
+fn trilinear(table: &Table, c: Color) -> Color {
+    let (corner, f) = cell(table, c);
+    let lerp = |a: Color, b: Color, t: f32| a + t * (b - a);
+    let face = |db| {
+        let front = lerp(corner(0, 0, db), corner(1, 0, db), f.x);
+        let back = lerp(corner(0, 1, db), corner(1, 1, db), f.x);
+        lerp(front, back, f.y)
+    };
+    lerp(face(0), face(1), f.z)
+}
+
+fn tetrahedral(table: &Table, c: Color) -> Color {
+    let (corner, f) = cell(table, c);
+    let (c000, c111) = (corner(0, 0, 0), corner(1, 1, 1));
+    // every tetrahedron runs from the black corner of the cell to the white
+    // one, through the two corners along the largest fractions
+    if f.x > f.y {
+        if f.y > f.z {
+            (1.0 - f.x) * c000
+                + (f.x - f.y) * corner(1, 0, 0)
+                + (f.y - f.z) * corner(1, 1, 0)
+                + f.z * c111
+        } else if f.x > f.z {
+            (1.0 - f.x) * c000
+                + (f.x - f.z) * corner(1, 0, 0)
+                + (f.z - f.y) * corner(1, 0, 1)
+                + f.y * c111
+        } else {
+            (1.0 - f.z) * c000
+                + (f.z - f.x) * corner(0, 0, 1)
+                + (f.x - f.y) * corner(1, 0, 1)
+                + f.y * c111
+        }
+    } else if f.z > f.y {
+        (1.0 - f.z) * c000
+            + (f.z - f.y) * corner(0, 0, 1)
+            + (f.y - f.x) * corner(0, 1, 1)
+            + f.x * c111
+    } else if f.z > f.x {
+        (1.0 - f.y) * c000
+            + (f.y - f.z) * corner(0, 1, 0)
+            + (f.z - f.x) * corner(0, 1, 1)
+            + f.x * c111
+    } else {
+        (1.0 - f.y) * c000
+            + (f.y - f.x) * corner(0, 1, 0)
+            + (f.x - f.z) * corner(1, 1, 0)
+            + f.z * c111
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    /// Loads a LUT from `text`, written to a temporary .cube file.
+    fn lut_from(name: &str, text: &str) -> Lut {
+        let path = env::temp_dir().join(format!("raytracer-{}-{}.cube", name, std::process::id()));
+        fs::write(&path, text).unwrap();
+        let result = std::panic::catch_unwind(|| Lut::from_file(path.to_str().unwrap()));
+        fs::remove_file(&path).unwrap();
+        match result {
+            Err(why) => std::panic::resume_unwind(why),
+            Ok(lut) => lut,
+        }
+    }
+
+    /// A 3D LUT of `size` whose entries come from `f` of the input color.
+    fn cube_text(size: usize, f: impl Fn(Color) -> Color) -> String {
+        let mut text = format!("LUT_3D_SIZE {}\n", size);
+        let scale = (size - 1) as f32;
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    let c = f(Color::new(r as f32, g as f32, b as f32) / scale);
+                    text += &format!("{} {} {}\n", c.x, c.y, c.z);
+                }
+            }
+        }
+        text
+    }
+
+    fn assert_close(a: Color, b: Color) {
+        assert!(
+            (a - b).length() < 1e-5,
+            "({}, {}, {}) != ({}, {}, {})",
+            a.x,
+            a.y,
+            a.z,
+            b.x,
+            b.y,
+            b.z
+        );
+    }
+
+    const COLORS: [(f32, f32, f32); 5] = [
+        (0.0, 0.0, 0.0),
+        (1.0, 1.0, 1.0),
+        (0.3, 0.6, 0.9),
+        (0.95, 0.1, 0.5),
+        (0.42, 0.42, 0.13),
+    ];
+
+    #[test]
+    fn identity_luts_leave_colors_unchanged() {
+        let lut = lut_from("identity", &cube_text(5, |c| c));
+        for (r, g, b) in COLORS {
+            let c = Color::new(r, g, b);
+            assert_close(lut.apply(c, Interpolation::Trilinear), c);
+            assert_close(lut.apply(c, Interpolation::Tetrahedral), c);
+        }
+    }
+
+    #[test]
+    fn tetrahedral_keeps_grays_neutral() {
+        // neutral along the gray axis, but not in between
+        let lut = lut_from(
+            "neutral",
+            &cube_text(5, |c| {
+                Color::new(c.x * c.x + (c.y - c.z).powi(2), c.y * c.y, c.z * c.z)
+            }),
+        );
+        let gray = Color::new(0.3, 0.3, 0.3);
+        let c = lut.apply(gray, Interpolation::Tetrahedral);
+        assert_eq!((c.x, c.y), (c.y, c.z));
+        let c = lut.apply(gray, Interpolation::Trilinear);
+        assert!(c.x > c.y);
+    }
+
+    #[test]
+    fn shapers_and_unknown_keywords_are_read() {
+        let text = format!(
+            "# comment\nTITLE \"test\"\nLUT_IN_VIDEO_RANGE\nLUT_1D_SIZE 2\n\
+             LUT_1D_INPUT_RANGE 0 2\nLUT_3D_INPUT_RANGE 0.0 1.0\n0 0 0\n1 1 1\n{}",
+            cube_text(2, |c| c)
+        );
+        let lut = lut_from("shaper", &text);
+        let c = lut.apply(Color::new(1.0, 0.5, 2.0), Interpolation::Tetrahedral);
+        assert_close(c, Color::new(0.5, 0.25, 1.0));
+    }
+
+    #[test]
+    #[should_panic(expected = "expected 1 value")]
+    fn sizes_need_a_value() {
+        lut_from("no-size", "LUT_3D_SIZE\n");
+    }
+}
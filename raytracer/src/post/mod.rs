@@ -0,0 +1,31 @@
+use crate::utils::vector::Color;
+
+pub mod denoise;
+pub mod effects;
+mod fft;
+mod glare;
+pub mod grade;
+pub mod lut;
+
+/// Linear image on its way from the film to the output, with rows stored
+/// bottom to top like the film.
+pub struct Image {
+    pub size: (u32, u32),
+    pub pixels: Vec<Color>,
+}
+
+impl Image {
+    pub fn new(size: (u32, u32), pixel: impl Fn((u32, u32)) -> Color) -> Image {
+        let mut pixels = Vec::with_capacity((size.0 * size.1) as usize);
+        for y in 0..size.1 {
+            for x in 0..size.0 {
+                pixels.push(pixel((x, y)));
+            }
+        }
+        Image { size, pixels }
+    }
+
+    pub fn get(&self, pos: (u32, u32)) -> Color {
+        self.pixels[(pos.0 + pos.1 * self.size.0) as usize]
+    }
+}
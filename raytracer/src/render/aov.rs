@@ -0,0 +1,149 @@
+use super::materials::material::Lobe;
+use crate::utils::vector::*;
+
+/// * Depth
+/// * Normal
+/// * Albedo
+/// * Position
+/// * MaterialId
+/// * ObjectId
+/// * DiffuseDirect
+/// * DiffuseIndirect
+/// * GlossyDirect
+/// * GlossyIndirect
+/// * TransmissionDirect
+/// * TransmissionIndirect
+///
+/// Arbitrary output variable: an image the sampler can write next to the
+/// beauty render, for compositing and denoising. Feature AOVs describe what
+/// the camera ray hit first and are black where it hit nothing.
+///
+/// The environment is the only light in a scene, so light that reaches the
+/// camera after a single bounce counts as direct, and light after more
+/// bounces as indirect. Contributions are split by the lobe of the first
+/// bounce.
+#[allow(dead_code)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum Aov {
+    /// Distance from the camera along the ray, in all three channels.
+    Depth,
+    /// World space normal, facing the camera.
+    Normal,
+    Albedo,
+    /// World space position.
+    Position,
+    /// Hash of the material's parameters, equal for identical materials.
+    MaterialId,
+    /// Index of the shape in the scene's components.
+    ObjectId,
+    DiffuseDirect,
+    DiffuseIndirect,
+    GlossyDirect,
+    GlossyIndirect,
+    TransmissionDirect,
+    TransmissionIndirect,
+}
+
+impl Aov {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::Position => "position",
+            Aov::MaterialId => "material_id",
+            Aov::ObjectId => "object_id",
+            Aov::DiffuseDirect => "diffuse_direct",
+            Aov::DiffuseIndirect => "diffuse_indirect",
+            Aov::GlossyDirect => "glossy_direct",
+            Aov::GlossyIndirect => "glossy_indirect",
+            Aov::TransmissionDirect => "transmission_direct",
+            Aov::TransmissionIndirect => "transmission_indirect",
+        }
+    }
+
+    /// Channels of this AOV's layer in multi-layer images, taken from the
+    /// components of its value in order.
+    pub fn channels(&self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::MaterialId | Aov::ObjectId => &["id"],
+            _ => &["R", "G", "B"],
+        }
+    }
+
+    /// Light AOVs add up to the beauty pass, so they get the same
+    /// reconstruction filter. Features are averaged over each pixel's own
+    /// samples instead, so filter lobes can't push them out of range.
+    pub fn is_light(&self) -> bool {
+        !self.is_id() && !self.is_feature()
+    }
+
+    pub fn is_feature(&self) -> bool {
+        matches!(self, Aov::Depth | Aov::Normal | Aov::Albedo | Aov::Position)
+    }
+
+    /// IDs can't be averaged, so each pixel keeps the ID its first sample
+    /// saw instead.
+    pub fn is_id(&self) -> bool {
+        matches!(self, Aov::MaterialId | Aov::ObjectId)
+    }
+
+    /// This AOV's value for one sample.
+    pub fn value(&self, sample: &PathSample) -> Color {
+        let black = Color::new(0.0, 0.0, 0.0);
+        let first_hit = match &sample.first_hit {
+            Some(first_hit) => first_hit,
+            None => return black,
+        };
+        let light = |lobe: Lobe, direct: bool| {
+            if first_hit.lobe == lobe && (sample.bounces == 1) == direct {
+                sample.color
+            } else {
+                black
+            }
+        };
+        match self {
+            Aov::Depth => Color::new(first_hit.dist, first_hit.dist, first_hit.dist),
+            Aov::Normal => first_hit.normal,
+            Aov::Albedo => first_hit.albedo,
+            Aov::Position => first_hit.p,
+            Aov::MaterialId => id_color(first_hit.material_id),
+            Aov::ObjectId => id_color(first_hit.object_id),
+            Aov::DiffuseDirect => light(Lobe::Diffuse, true),
+            Aov::DiffuseIndirect => light(Lobe::Diffuse, false),
+            Aov::GlossyDirect => light(Lobe::Glossy, true),
+            Aov::GlossyIndirect => light(Lobe::Glossy, false),
+            Aov::TransmissionDirect => light(Lobe::Transmission, true),
+            Aov::TransmissionIndirect => light(Lobe::Transmission, false),
+        }
+    }
+}
+
+/// What the camera ray of a sample hit.
+pub struct FirstHit {
+    pub dist: f32,
+    pub normal: Vec3,
+    pub albedo: Color,
+    pub p: Point3,
+    pub material_id: u32,
+    pub object_id: u32,
+    /// Lobe the path scattered into there.
+    pub lobe: Lobe,
+}
+
+/// Result of tracing one path, with everything the AOVs are made from.
+pub struct PathSample {
+    pub first_hit: Option<FirstHit>,
+    /// Bounces the path took before it escaped, if it did.
+    pub bounces: u32,
+    /// Light the path carried back.
+    pub color: Color,
+}
+
+/// IDs go in every channel, kept below 2^24 so they stay exact as floats.
+fn id_color(id: u32) -> Color {
+    let id = (id & 0xff_ffff) as f32;
+    Color::new(id, id, id)
+}
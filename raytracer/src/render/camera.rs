@@ -1,74 +1,895 @@
-use rand::prelude::*;
+use super::{lens::LensSystem, sample_generator::SampleGenerator};
+use crate::utils::{color::white_balance_gains, color_space::ColorSpace, ray::Ray, vector::*};
 
-use crate::utils::{ray::Ray, vector::*};
+const PI: f32 = std::f32::consts::PI;
+const TAU: f32 = 2.0 * PI;
+
+/// Height of a 35mm full-frame sensor in world units (meters), used to turn
+/// the field of view into a focal length.
+const SENSOR_HEIGHT: f32 = 0.024;
+/// Reflected-light meter calibration constant for saturation-based exposure.
+const METER_CALIBRATION: f32 = 1.2;
+/// Least mean transmittance an aperture mask needs over the unit disk.
+const MIN_MASK_TRANSMITTANCE: f32 = 0.05;
+/// Points tried per sample of an aperture mask before giving up.
+const MAX_MASK_ATTEMPTS: u32 = 256;
+
+/// * Perspective(fov: Fov)
+/// * Orthographic(height: f32)
+/// * Fisheye(fov: f32, mapping: FisheyeMapping)
+/// * Equirectangular
+/// * Realistic(lens: LensSystem)
+///
+/// Angles are in degrees. Only the perspective and realistic projections
+/// simulate a lens; the others are pinholes and ignore the f-number's depth
+/// of field.
+#[allow(dead_code)]
+pub enum Projection {
+    Perspective {
+        fov: Fov,
+    },
+    /// Parallel rays; `height` is the height of the view in world units.
+    Orthographic {
+        height: f32,
+    },
+    /// Circular fisheye inscribed in the image; `fov` spans the image circle.
+    Fisheye {
+        fov: f32,
+        mapping: FisheyeMapping,
+    },
+    /// Full 360° by 180° latitude-longitude panorama around the camera.
+    Equirectangular,
+    /// Rays traced through the glass of a real lens onto a full-frame sensor,
+    /// giving its distortion, vignetting and focus breathing. The field of
+    /// view follows from the lens; the f-number stops it down, but never
+    /// beyond the prescription's widest aperture.
+    Realistic {
+        lens: LensSystem,
+    },
+}
+
+/// Field of view of the perspective projection in degrees, measured along
+/// either image axis; the other follows from the aspect ratio.
+#[allow(dead_code)]
+pub enum Fov {
+    Vertical(f32),
+    Horizontal(f32),
+}
+
+/// * Point(Point3)
+/// * Distance(f32)
+/// * Auto
+///
+/// Where the lens is focused.
+#[allow(dead_code)]
+pub enum Focus {
+    /// Focus at the distance of a point in the scene.
+    Point(Point3),
+    /// Focus at a fixed distance from the camera.
+    Distance(f32),
+    /// Focus on whatever the center of the frame sees, found by casting a
+    /// ray once the scene is built. Falls back to `lookat` on a miss.
+    Auto,
+}
+
+/// How a fisheye maps the angle from the view axis to the image radius.
+#[allow(dead_code)]
+pub enum FisheyeMapping {
+    /// Radius proportional to the angle, as used by dome projection.
+    Equidistant,
+    /// Radius proportional to sin(angle / 2), preserving solid angle.
+    Equisolid,
+}
+
+/// * Mono
+/// * Pair(interocular: f32, convergence: f32, layout: StereoLayout)
+/// * Omni(interocular: f32, layout: StereoLayout)
+///
+/// Stereo modes render both eyes into one image, split according to `layout`.
+#[allow(dead_code)]
+pub enum Stereo {
+    Mono,
+    /// Two views offset along the camera's right axis. For the perspective
+    /// projection the frusta are sheared so they coincide at `convergence`
+    /// distance (zero parallax); other projections are only translated.
+    Pair {
+        interocular: f32,
+        convergence: f32,
+        layout: StereoLayout,
+    },
+    /// Omni-directional stereo for the equirectangular projection: each ray's
+    /// origin sits on a circle of diameter `interocular`, tangent to the ray.
+    /// The offset fades out towards the poles to keep them comfortable.
+    Omni {
+        interocular: f32,
+        layout: StereoLayout,
+    },
+}
+
+/// Where each eye goes in the output image.
+#[allow(dead_code)]
+pub enum StereoLayout {
+    /// Left eye in the left half, right eye in the right half.
+    SideBySide,
+    /// Left eye in the top half, right eye in the bottom half.
+    TopBottom,
+}
+
+impl Stereo {
+    /// Splits an image coordinate into the eye it belongs to (-1 for left,
+    /// 1 for right, 0 for mono) and the coordinate within that eye's view.
+    fn eye(&self, u: f32, v: f32) -> (f32, f32, f32) {
+        let layout = match self {
+            Stereo::Mono => return (0.0, u, v),
+            Stereo::Pair { layout, .. } => layout,
+            Stereo::Omni { layout, .. } => layout,
+        };
+        match layout {
+            StereoLayout::SideBySide if u < 0.5 => (-1.0, 2.0 * u, v),
+            StereoLayout::SideBySide => (1.0, 2.0 * u - 1.0, v),
+            StereoLayout::TopBottom if v >= 0.5 => (-1.0, u, 2.0 * v - 1.0),
+            StereoLayout::TopBottom => (1.0, u, 2.0 * v),
+        }
+    }
+
+    /// Aspect ratio of a single eye's view within an image of `aspect_ratio`.
+    fn eye_aspect_ratio(&self, aspect_ratio: f32) -> f32 {
+        match self {
+            Stereo::Mono => aspect_ratio,
+            Stereo::Pair { layout, .. } | Stereo::Omni { layout, .. } => match layout {
+                StereoLayout::SideBySide => aspect_ratio / 2.0,
+                StereoLayout::TopBottom => aspect_ratio * 2.0,
+            },
+        }
+    }
+}
+
+/// * Circular
+/// * Polygonal(blades: u32, rotation: f32)
+/// * Mask(texture: Vec<f32>, size: (u32, u32))
+///
+/// Shape of the lens opening, which is the shape out-of-focus highlights take.
+#[allow(dead_code)]
+pub enum Aperture {
+    Circular,
+    /// Regular polygon formed by `blades` straight blades, rotated by
+    /// `rotation` degrees.
+    Polygonal {
+        blades: u32,
+        rotation: f32,
+    },
+    /// Grayscale transmittance image covering the lens, row-major from the top.
+    Mask {
+        texture: Vec<f32>,
+        size: (u32, u32),
+    },
+}
+
+impl Aperture {
+    /// Loads an image as an aperture mask; brighter pixels let more light through.
+    #[allow(dead_code)]
+    pub fn from_file(file_path: &str) -> Aperture {
+        let img = match image::open(file_path) {
+            Err(why) => panic!("couldn't open {}: {}", file_path, why),
+            Ok(img) => img.to_luma8(),
+        };
+        let size = img.dimensions();
+        let texture = img.pixels().map(|p| p.0[0] as f32 / 255.0).collect();
+        let aperture = Aperture::Mask { texture, size };
+
+        // sampling rejects points where the mask is dark, which would take
+        // forever on a nearly black one
+        let (mut sum, mut count) = (0.0, 0);
+        for y in 0..size.1 {
+            for x in 0..size.0 {
+                let u = 2.0 * (x as f32 + 0.5) / size.0 as f32 - 1.0;
+                let v = 1.0 - 2.0 * (y as f32 + 0.5) / size.1 as f32;
+                if u * u + v * v <= 1.0 {
+                    sum += aperture.transmittance(u, v);
+                    count += 1;
+                }
+            }
+        }
+        if count == 0 || sum / (count as f32) < MIN_MASK_TRANSMITTANCE {
+            panic!("aperture mask {} lets almost no light through", file_path);
+        }
+        aperture
+    }
+
+    /// How much light gets through at a point of the unit disk, with x
+    /// pointing right and y up.
+    pub fn transmittance(&self, x: f32, y: f32) -> f32 {
+        if x * x + y * y > 1.0 {
+            return 0.0;
+        }
+        match self {
+            Aperture::Circular => 1.0,
+            Aperture::Polygonal { blades, rotation } => {
+                // distance from the center along the normal of the nearest
+                // blade, against the polygon's inner radius
+                let angle = TAU / *blades as f32;
+                let offset = (y.atan2(x) - rotation.to_radians()).rem_euclid(angle);
+                let r = (x * x + y * y).sqrt();
+                let along_normal = r * (offset - angle / 2.0).cos();
+                if along_normal <= (angle / 2.0).cos() {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Aperture::Mask { texture, size } => {
+                let px = ((x + 1.0) / 2.0 * size.0 as f32) as u32;
+                let py = ((1.0 - y) / 2.0 * size.1 as f32) as u32;
+                texture[(px.min(size.0 - 1) + py.min(size.1 - 1) * size.0) as usize]
+            }
+        }
+    }
+
+    /// Uniformly samples a point on the opening, within the unit disk.
+    fn sample(&self, gen: &mut SampleGenerator) -> Vec3 {
+        match self {
+            Aperture::Circular => gen.in_unit_disk(),
+            Aperture::Polygonal { blades, rotation } => {
+                random_in_polygon(*blades, rotation.to_radians(), gen)
+            }
+            Aperture::Mask { texture, size } => random_in_mask(texture, *size, gen),
+        }
+    }
+}
+
+pub struct CameraSettings {
+    pub origin: Point3,
+    pub lookat: Point3,
+    /// World direction that appears upwards in the image. It does not need
+    /// to be perpendicular to the view direction, and a parallel one falls
+    /// back to another axis instead of breaking the camera.
+    pub vup: Vec3,
+    /// Counter-clockwise rotation of the image around the view direction, in
+    /// degrees, for dutch angles.
+    pub roll: f32,
+    pub projection: Projection,
+    /// Width over height of the whole output image, including every eye.
+    pub aspect_ratio: f32,
+    /// Focal length over aperture diameter. Sets both the lens radius used
+    /// for depth of field and, with `shutter_speed` and `iso`, the exposure.
+    pub f_stop: f32,
+    pub focus: Focus,
+    /// Times at which the shutter opens and closes, in seconds. Each ray is
+    /// assigned a random time in between, which blurs moving shapes. Equal
+    /// times freeze motion.
+    pub shutter: (f32, f32),
+    /// Exposure time in seconds. Only sets the brightness, so it can differ
+    /// from the length of `shutter`.
+    pub shutter_speed: f32,
+    /// Sensor sensitivity; ISO 100 is the reference.
+    pub iso: f32,
+    /// Color temperature in kelvin that renders as neutral white.
+    pub white_balance: f32,
+    /// Color space of the render, which the white balance gains apply in.
+    pub working_space: ColorSpace,
+    pub aperture: Aperture,
+    /// Optical vignetting: how far the lens barrel clips the aperture at the
+    /// top edge of the frame, as a fraction of its radius. Highlights turn
+    /// into cat's eyes and the corners darken; 0 disables it.
+    pub cat_eye: f32,
+    /// Lateral chromatic aberration: how much larger the red image is than
+    /// the green one (and the blue one smaller), as a fraction. 0 disables it.
+    pub chromatic_aberration: f32,
+    /// Lens shift as a fraction of the frame's width and height. Moves the
+    /// framing without turning the camera, so vertical lines stay parallel.
+    pub shift: (f32, f32),
+    /// Scheimpflug tilt of the plane of focus in degrees: around the
+    /// horizontal axis (positive leans the top away) and around the vertical
+    /// axis (positive leans the right side away).
+    pub tilt: (f32, f32),
+    pub stereo: Stereo,
+}
 
 pub struct Camera {
     origin: Point3,
+    /// Viewport extents; at unit distance for perspective, in world units
+    /// for orthographic.
     horizontal: Vec3,
     vertical: Vec3,
-    lower_left_corner: Point3,
     lens_radius: f32,
     u: Vec3,
     v: Vec3,
+    w: Vec3,
+    aspect_ratio: f32,
+    focus_dist: f32,
+    f_stop: f32,
+    /// Normal of the plane of focus, which passes `focus_dist` in front of
+    /// the camera.
+    focal_normal: Vec3,
+    autofocus: bool,
+    projection: Projection,
+    stereo: Stereo,
+    exposure: f32,
+    white_balance: Color,
+    aperture: Aperture,
+    cat_eye: f32,
+    chromatic_aberration: f32,
+    shift: (f32, f32),
+    pub shutter: (f32, f32),
+}
+
+/// F-stop of a perspective camera with the vertical field of view `vfov`, in
+/// degrees, whose lens is `diameter` world units across.
+pub fn f_stop_for_lens(vfov: f32, diameter: f32) -> f32 {
+    let focal_length = SENSOR_HEIGHT / (2.0 * (vfov.to_radians() / 2.0).tan());
+    focal_length / diameter
+}
+
+/// ISO at which `f_stop` and `shutter_speed` leave radiance unscaled.
+pub fn unit_exposure_iso(f_stop: f32, shutter_speed: f32) -> f32 {
+    METER_CALIBRATION * f_stop * f_stop / shutter_speed * 100.0
+}
+
+fn random_in_polygon(blades: u32, rotation: f32, gen: &mut SampleGenerator) -> Vec3 {
+    // pick one of the triangles fanning out from the center, then a point in it
+    let blade = (gen.get_1d() * blades as f32) as u32 % blades;
+    let angle = TAU / blades as f32;
+    let a0 = rotation + blade as f32 * angle;
+    let a1 = a0 + angle;
+
+    let (mut s, mut t) = gen.get_2d();
+    if s + t > 1.0 {
+        s = 1.0 - s;
+        t = 1.0 - t;
+    }
+    Vec3::new(
+        s * a0.cos() + t * a1.cos(),
+        s * a0.sin() + t * a1.sin(),
+        0.0,
+    )
 }
 
-fn random_in_unit_disk() -> Vec3 {
-    loop {
-        let r1: f32 = random::<f32>() * 2.0 - 1.0;
-        let r2: f32 = random::<f32>() * 2.0 - 1.0;
-        let p = Vec3::new(r1, r2, 0.0);
-        if p.length_squared() < 1.0 {
+/// Rejection samples the mask. Masks that keep rejecting for
+/// `MAX_MASK_ATTEMPTS` points get their clearest texel instead, so samples
+/// always land on the opening.
+fn random_in_mask(texture: &[f32], size: (u32, u32), gen: &mut SampleGenerator) -> Vec3 {
+    let transmittance = |p: Vec3| {
+        let x = ((p.x + 1.0) / 2.0 * size.0 as f32) as u32;
+        let y = ((1.0 - p.y) / 2.0 * size.1 as f32) as u32;
+        texture[(x.min(size.0 - 1) + y.min(size.1 - 1) * size.0) as usize]
+    };
+    for _ in 0..MAX_MASK_ATTEMPTS {
+        let p = gen.in_unit_disk();
+        if gen.get_1d() < transmittance(p) {
             return p;
         }
     }
+
+    let mut clearest = (0.0, Vec3::new(0.0, 0.0, 0.0));
+    for y in 0..size.1 {
+        for x in 0..size.0 {
+            let u = 2.0 * (x as f32 + 0.5) / size.0 as f32 - 1.0;
+            let v = 1.0 - 2.0 * (y as f32 + 0.5) / size.1 as f32;
+            let t = texture[(x + y * size.0) as usize];
+            if u * u + v * v <= 1.0 && t > clearest.0 {
+                clearest = (t, Vec3::new(u, v, 0.0));
+            }
+        }
+    }
+    clearest.1
+}
+
+/// Right and up axes for a camera looking along `-w`, rotated by `roll`
+/// radians around it.
+fn camera_axes(w: Vec3, vup: Vec3, roll: f32) -> (Vec3, Vec3) {
+    let mut right = cross(vup, w);
+    if right.length_squared() < 1e-12 {
+        // looking straight along vup; any other axis works as a reference
+        let fallback = if w.x.abs() < 0.9 {
+            Vec3::new(1.0, 0.0, 0.0)
+        } else {
+            Vec3::new(0.0, 0.0, 1.0)
+        };
+        right = cross(fallback, w);
+    }
+    let u = right.unit();
+    let v = cross(w, u);
+
+    (
+        roll.cos() * u + roll.sin() * v,
+        roll.cos() * v - roll.sin() * u,
+    )
 }
 
 impl Camera {
-    pub fn new(
-        origin: Point3,
-        lookat: Point3,
-        vfov: f32,
-        aspect_ratio: f32,
-        aperture: f32,
-        focal_point: Point3,
-    ) -> Camera {
-        let focus_dist = (origin - focal_point).length();
-
-        let theta = vfov.to_radians();
-        let h = (theta / 2.0).tan();
-        let viewport_height = 2.0 * h;
-        let viewport_width = aspect_ratio * viewport_height;
+    pub fn new(settings: CameraSettings) -> Camera {
+        let CameraSettings {
+            origin,
+            lookat,
+            vup,
+            roll,
+            projection,
+            aspect_ratio,
+            f_stop,
+            focus,
+            shutter,
+            shutter_speed,
+            iso,
+            white_balance,
+            working_space,
+            aperture,
+            cat_eye,
+            chromatic_aberration,
+            shift,
+            tilt,
+            stereo,
+        } = settings;
+
+        let mut projection = projection;
+        let equirectangular = matches!(projection, Projection::Equirectangular);
+        if matches!(stereo, Stereo::Omni { .. }) && !equirectangular {
+            panic!("omni-directional stereo requires an equirectangular projection");
+        }
 
-        let vup = Vec3::new(0.0, 1.0, 0.0);
+        let aspect_ratio = stereo.eye_aspect_ratio(aspect_ratio);
+        let focus_dist = match focus {
+            Focus::Point(focal_point) => (origin - focal_point).length(),
+            Focus::Distance(dist) => dist,
+            Focus::Auto => (origin - lookat).length(),
+        };
+
+        if let Projection::Realistic { ref mut lens } = projection {
+            lens.focus(focus_dist, f_stop);
+        }
 
         let w = (origin - lookat).unit();
-        let u = cross(vup, w).unit();
-        let v = cross(w, u);
+        let (u, v) = camera_axes(w, vup, roll.to_radians());
+
+        // the viewport and lens only matter for the planar projections
+        let (viewport_height, lens_radius) = match projection {
+            Projection::Perspective { ref fov } => {
+                let h = match fov {
+                    Fov::Vertical(vfov) => (vfov.to_radians() / 2.0).tan(),
+                    Fov::Horizontal(hfov) => (hfov.to_radians() / 2.0).tan() / aspect_ratio,
+                };
+                let focal_length = SENSOR_HEIGHT / (2.0 * h);
+                (2.0 * h, focal_length / (2.0 * f_stop))
+            }
+            Projection::Orthographic { height } => (height, 0.0),
+            _ => (0.0, 0.0),
+        };
+        let viewport_width = aspect_ratio * viewport_height;
 
-        let horizontal = focus_dist * viewport_width * u;
-        let vertical = focus_dist * viewport_height * v;
-        let lower_left_corner = origin - horizontal / 2.0 - vertical / 2.0 - focus_dist * w;
+        let horizontal = viewport_width * u;
+        let vertical = viewport_height * v;
 
-        let lens_radius = aperture / 2.0;
+        let focal_normal =
+            (w + tilt.0.to_radians().tan() * v + tilt.1.to_radians().tan() * u).unit();
+
+        // saturation-based exposure: radiance at the clipping point of the
+        // sensor maps to 1.0
+        if shutter_speed <= 0.0 {
+            panic!("shutter speed has to be positive, not {}", shutter_speed);
+        }
+        let max_luminance = METER_CALIBRATION * (f_stop * f_stop / shutter_speed) * (100.0 / iso);
+        let exposure = 1.0 / max_luminance;
 
-        let rng = rand::thread_rng();
         Camera {
             origin,
             horizontal,
             vertical,
-            lower_left_corner,
             lens_radius,
             u,
             v,
+            w,
+            aspect_ratio,
+            focus_dist,
+            f_stop,
+            focal_normal,
+            autofocus: matches!(focus, Focus::Auto),
+            projection,
+            stereo,
+            exposure,
+            white_balance: white_balance_gains(white_balance, working_space),
+            aperture,
+            cat_eye,
+            chromatic_aberration,
+            shift,
+            shutter,
+        }
+    }
+
+    /// Ray through the center of the frame, which autofocus measures along.
+    /// Returns `None` unless the camera was set to `Focus::Auto`.
+    pub fn autofocus_ray(&self) -> Option<Ray> {
+        if !self.autofocus {
+            return None;
+        }
+        Some(Ray::new(self.origin, -self.w, self.shutter.0))
+    }
+
+    pub fn set_focus_dist(&mut self, focus_dist: f32) {
+        self.focus_dist = focus_dist;
+        if let Projection::Realistic { ref mut lens } = self.projection {
+            lens.focus(focus_dist, self.f_stop);
+        }
+    }
+
+    pub fn aperture(&self) -> &Aperture {
+        &self.aperture
+    }
+
+    /// Converts scene radiance into what the sensor records: scaled by the
+    /// exposure and white balanced.
+    pub fn expose(&self, radiance: Color) -> Color {
+        self.exposure * radiance * self.white_balance
+    }
+
+    /// Matrix from world space to camera space, where the camera looks down
+    /// -z with y up. Matrices multiply row vectors from the left, as in
+    /// OpenEXR's metadata.
+    pub fn world_to_camera(&self) -> [[f32; 4]; 4] {
+        let (u, v, w, o) = (self.u, self.v, self.w, self.origin);
+        [
+            [u.x, v.x, w.x, 0.0],
+            [u.y, v.y, w.y, 0.0],
+            [u.z, v.z, w.z, 0.0],
+            [-dot(o, u), -dot(o, v), -dot(o, w), 1.0],
+        ]
+    }
+
+    /// Matrix from world space to normalized device coordinates, where the
+    /// frame spans -1 to 1 on both axes after dividing by w. Only perspective
+    /// projections of the center eye have one.
+    pub fn world_to_ndc(&self) -> Option<[[f32; 4]; 4]> {
+        if !matches!(self.projection, Projection::Perspective { .. }) {
+            return None;
+        }
+        // x / -z spans the viewport width at unit depth, offset by the shift
+        let sx = 2.0 / self.horizontal.length();
+        let sy = 2.0 / self.vertical.length();
+        let (tx, ty) = (2.0 * self.shift.0, 2.0 * self.shift.1);
+        let projection = [
+            [sx, 0.0, 0.0, 0.0],
+            [0.0, sy, 0.0, 0.0],
+            [tx, ty, 0.0, -1.0],
+            [0.0, 0.0, 1.0, 0.0],
+        ];
+
+        let view = self.world_to_camera();
+        let mut out = [[0.0; 4]; 4];
+        for (i, row) in out.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = (0..4).map(|k| view[i][k] * projection[k][j]).sum();
+            }
+        }
+        Some(out)
+    }
+
+    /// Returns the ray through the image coordinate (u, v) along with the
+    /// color it is weighted by, or `None` if no ray reaches it, such as
+    /// outside a fisheye's image circle or when vignetted.
+    pub fn get_ray(&self, u: f32, v: f32, gen: &mut SampleGenerator) -> Option<(Ray, Color)> {
+        let time = self.shutter.0 + gen.get_1d() * (self.shutter.1 - self.shutter.0);
+        let (eye, u, v) = self.stereo.eye(u, v);
+        let (u, v) = (u + self.shift.0, v + self.shift.1);
+
+        // trace a single channel, magnified by its own amount
+        let (u, v, mut weight) = if self.chromatic_aberration != 0.0 {
+            let channel = (gen.get_1d() * 3.0) as usize % 3;
+            let (scale, weight) = match channel {
+                0 => (1.0 - self.chromatic_aberration, Color::new(3.0, 0.0, 0.0)),
+                1 => (1.0, Color::new(0.0, 3.0, 0.0)),
+                _ => (1.0 + self.chromatic_aberration, Color::new(0.0, 0.0, 3.0)),
+            };
+            (0.5 + (u - 0.5) * scale, 0.5 + (v - 0.5) * scale, weight)
+        } else {
+            (u, v, Color::new(1.0, 1.0, 1.0))
+        };
+
+        let (origin, direction) = match self.projection {
+            Projection::Perspective { .. } => {
+                let lens = self.aperture.sample(gen);
+                if self.cat_eye != 0.0 {
+                    // the barrel is a second opening, shifted towards the frame center
+                    let x = (2.0 * u - 1.0) * self.aspect_ratio;
+                    let y = 2.0 * v - 1.0;
+                    let barrel = lens - self.cat_eye * Vec3::new(x, y, 0.0);
+                    if barrel.length_squared() > 1.0 {
+                        return None;
+                    }
+                }
+
+                let rd = self.lens_radius * lens;
+                let offset = self.u * rd.x + self.v * rd.y;
+                // pinhole direction, one unit deep
+                let pinhole = (u - 0.5) * self.horizontal + (v - 0.5) * self.vertical - self.w;
+
+                let (eye_origin, pinhole) = match self.stereo {
+                    Stereo::Pair {
+                        interocular,
+                        convergence,
+                        ..
+                    } => {
+                        // shear the eye's frustum so both views meet at the convergence plane
+                        let converged = self.origin + convergence * pinhole;
+                        let eye_origin = self.origin + (eye * interocular / 2.0) * self.u;
+                        (eye_origin, (converged - eye_origin) / convergence)
+                    }
+                    _ => (self.origin, pinhole),
+                };
+
+                // aim the lens sample at where the pinhole ray meets the plane of focus
+                let plane_point = self.origin - self.focus_dist * self.w;
+                let t = dot(plane_point - eye_origin, self.focal_normal)
+                    / dot(pinhole, self.focal_normal);
+                if t > 0.0 {
+                    let focus = eye_origin + t * pinhole;
+                    (eye_origin + offset, focus - eye_origin - offset)
+                } else {
+                    // the plane of focus is behind this ray, so it's focused at infinity
+                    (eye_origin + offset, pinhole)
+                }
+            }
+            Projection::Orthographic { .. } => (
+                self.origin + (u - 0.5) * self.horizontal + (v - 0.5) * self.vertical,
+                -self.w,
+            ),
+            Projection::Fisheye { fov, ref mapping } => {
+                // normalized so the image circle touches the top and bottom edges
+                let x = (2.0 * u - 1.0) * self.aspect_ratio;
+                let y = 2.0 * v - 1.0;
+                let r = (x * x + y * y).sqrt();
+                if r > 1.0 {
+                    return None;
+                }
+
+                let max_theta = fov.to_radians() / 2.0;
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => r * max_theta,
+                    FisheyeMapping::Equisolid => 2.0 * (r * (max_theta / 2.0).sin()).asin(),
+                };
+                let phi = y.atan2(x);
+
+                let direction =
+                    theta.sin() * (phi.cos() * self.u + phi.sin() * self.v) - theta.cos() * self.w;
+                (self.origin, direction)
+            }
+            Projection::Realistic { ref lens } => {
+                // the lens flips the image, so the film is sampled upside down
+                let film = Vec3::new(
+                    (0.5 - u) * self.aspect_ratio * SENSOR_HEIGHT,
+                    (0.5 - v) * SENSOR_HEIGHT,
+                    0.0,
+                );
+                let (o, d, lens_weight) = lens.sample_ray(film, gen)?;
+                weight = lens_weight * weight;
+
+                (
+                    self.origin + o.x * self.u + o.y * self.v - o.z * self.w,
+                    d.x * self.u + d.y * self.v - d.z * self.w,
+                )
+            }
+            Projection::Equirectangular => {
+                // longitude wraps around with the view direction at the image center
+                let phi = (u - 0.5) * TAU;
+                let theta = (v - 0.5) * PI;
+
+                let direction =
+                    theta.cos() * (phi.sin() * self.u - phi.cos() * self.w) + theta.sin() * self.v;
+
+                let origin = match self.stereo {
+                    Stereo::Omni { interocular, .. } => {
+                        let tangent = phi.cos() * self.u + phi.sin() * self.w;
+                        self.origin + (eye * interocular / 2.0 * theta.cos()) * tangent
+                    }
+                    _ => self.origin,
+                };
+                (origin, direction)
+            }
+        };
+
+        let origin = match (&self.stereo, &self.projection) {
+            (Stereo::Pair { interocular, .. }, Projection::Orthographic { .. })
+            | (Stereo::Pair { interocular, .. }, Projection::Fisheye { .. })
+            | (Stereo::Pair { interocular, .. }, Projection::Equirectangular)
+            | (Stereo::Pair { interocular, .. }, Projection::Realistic { .. }) => {
+                origin + (eye * interocular / 2.0) * self.u
+            }
+            _ => origin,
+        };
+
+        Some((Ray::new(origin, direction, time), weight))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::sample_generator::SamplePattern;
+
+    fn generator() -> SampleGenerator {
+        SampleGenerator::new(SamplePattern::Independent, (0, 0), 1024, 0)
+    }
+
+    /// An 8 by 8 mask that is open only on its texels in `open`.
+    fn mask(open: &[(u32, u32)]) -> Aperture {
+        let mut texture = vec![0.0; 64];
+        for &(x, y) in open {
+            texture[(x + 8 * y) as usize] = 1.0;
+        }
+        Aperture::Mask {
+            texture,
+            size: (8, 8),
+        }
+    }
+
+    /// A pinhole-like camera at `origin` looking at `lookat`, focused 5
+    /// units away.
+    fn settings(origin: Point3, lookat: Point3) -> CameraSettings {
+        CameraSettings {
+            origin,
+            lookat,
+            vup: Vec3::new(0.0, 1.0, 0.0),
+            roll: 0.0,
+            projection: Projection::Perspective {
+                fov: Fov::Vertical(40.0),
+            },
+            aspect_ratio: 1.5,
+            f_stop: 1e6,
+            focus: Focus::Distance(5.0),
+            shutter: (0.0, 0.0),
+            shutter_speed: 1.0,
+            iso: 100.0,
+            white_balance: 6500.0,
+            working_space: ColorSpace::Rec709,
+            aperture: Aperture::Circular,
+            cat_eye: 0.0,
+            chromatic_aberration: 0.0,
+            shift: (0.0, 0.0),
+            tilt: (0.0, 0.0),
+            stereo: Stereo::Mono,
+        }
+    }
+
+    fn ray(camera: &Camera, u: f32, v: f32) -> Ray {
+        camera.get_ray(u, v, &mut generator()).unwrap().0
+    }
+
+    /// How far `p` is from the line along `r`.
+    fn distance_to_ray(p: Point3, r: &Ray) -> f32 {
+        let to_p = p - r.origin;
+        (to_p - dot(to_p, r.direction) * r.direction).length()
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!(
+            (a - b).length() < 1e-4,
+            "({}, {}, {}) != ({}, {}, {})",
+            a.x,
+            a.y,
+            a.z,
+            b.x,
+            b.y,
+            b.z
+        );
+    }
+
+    #[test]
+    fn mask_samples_land_on_the_opening() {
+        // a wide opening, found by rejection, and a pinhole that needs the
+        // fallback most of the time
+        let wide: Vec<(u32, u32)> = (0..64)
+            .map(|i| (i % 8, i / 8))
+            .filter(|p| p.0 >= 4)
+            .collect();
+        for aperture in [mask(&wide), mask(&[(2, 5)])] {
+            let mut gen = generator();
+            for i in 0..1000 {
+                gen.start_sample(i);
+                let p = aperture.sample(&mut gen);
+                assert!(aperture.transmittance(p.x, p.y) > 0.0, "{} {}", p.x, p.y);
+            }
+        }
+    }
+
+    #[test]
+    fn shift_moves_the_frame_without_turning_the_camera() {
+        let (origin, lookat) = (Point3::new(1.0, 2.0, 3.0), Point3::new(4.0, 1.0, -2.0));
+        let camera = Camera::new(settings(origin, lookat));
+        let shifted = Camera::new(CameraSettings {
+            shift: (0.25, -0.1),
+            ..settings(origin, lookat)
+        });
+
+        assert_eq!(shifted.world_to_camera(), camera.world_to_camera());
+        // the center of the shifted frame shows what was off center before
+        let center = ray(&shifted, 0.5, 0.5);
+        assert_close(center.direction, ray(&camera, 0.75, 0.4).direction);
+        assert!(dot(center.direction, -camera.w) < 0.99);
+    }
+
+    #[test]
+    fn tilt_moves_the_plane_of_focus() {
+        let (origin, lookat) = (Point3::new(0.0, 1.0, 0.0), Point3::new(0.0, 1.0, -10.0));
+        let wide_open = |tilt| {
+            Camera::new(CameraSettings {
+                f_stop: 1.0,
+                tilt,
+                ..settings(origin, lookat)
+            })
+        };
+        let (camera, tilted) = (wide_open((0.0, 0.0)), wide_open((20.0, 0.0)));
+
+        assert_close(camera.focal_normal, camera.w);
+        let normal = tilted.focal_normal;
+        assert!((dot(normal, tilted.v) - 20f32.to_radians().sin()).abs() < 1e-5);
+        assert!(dot(normal, tilted.u).abs() < 1e-6);
+
+        // every lens sample of a pixel meets on the plane of focus, which
+        // still passes through the focus distance on the axis
+        let plane_point = origin - 5.0 * tilted.w;
+        for (u, v) in [(0.5, 0.5), (0.5, 0.9), (0.2, 0.1)] {
+            let pinhole = ray(&Camera::new(settings(origin, lookat)), u, v);
+            let t = dot(plane_point - origin, normal) / dot(pinhole.direction, normal);
+            let focus = pinhole.at(t);
+            let mut gen = generator();
+            for i in 0..16 {
+                gen.start_sample(i);
+                let (r, _) = tilted.get_ray(u, v, &mut gen).unwrap();
+                assert!(distance_to_ray(focus, &r) < 1e-4);
+            }
         }
+        // the top of the frame is focused further away than without tilt
+        let top = |camera: &Camera| {
+            let r = ray(camera, 0.5, 0.9);
+            dot(plane_point - origin, camera.focal_normal) / dot(r.direction, camera.focal_normal)
+        };
+        assert!(top(&tilted) > top(&camera) + 0.5);
+    }
+
+    #[test]
+    fn stereo_eyes_sit_half_the_interocular_distance_either_side() {
+        let (origin, lookat) = (Point3::new(1.0, 2.0, 3.0), Point3::new(4.0, 1.0, -2.0));
+        let camera = Camera::new(CameraSettings {
+            stereo: Stereo::Pair {
+                interocular: 0.064,
+                convergence: 2.0,
+                layout: StereoLayout::SideBySide,
+            },
+            ..settings(origin, lookat)
+        });
+
+        let (left, right) = (ray(&camera, 0.25, 0.5), ray(&camera, 0.75, 0.5));
+        assert_close(left.origin, origin - 0.032 * camera.u);
+        assert_close(right.origin, origin + 0.032 * camera.u);
+        // both views of the image center meet at the convergence distance
+        let converged = origin - 2.0 * camera.w;
+        assert!(distance_to_ray(converged, &left) < 1e-4);
+        assert!(distance_to_ray(converged, &right) < 1e-4);
     }
 
-    pub fn get_ray(&self, u: f32, v: f32) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disk();
-        let offset = self.u * rd.x + self.v * rd.y;
-        Ray::new(
-            self.origin + offset,
-            self.lower_left_corner + u * self.horizontal + v * self.vertical - self.origin - offset,
-        )
+    #[test]
+    fn omni_stereo_eyes_meet_at_the_poles() {
+        let origin = Point3::new(1.0, 2.0, 3.0);
+        let camera = Camera::new(CameraSettings {
+            projection: Projection::Equirectangular,
+            aspect_ratio: 1.0,
+            stereo: Stereo::Omni {
+                interocular: 0.064,
+                layout: StereoLayout::SideBySide,
+            },
+            ..settings(origin, Point3::new(1.0, 2.0, 0.0))
+        });
+
+        for u in [0.1, 0.3, 0.6, 0.85] {
+            // straight up and straight down both eyes look from the center
+            assert_close(ray(&camera, u, 0.0).origin, origin);
+            assert_close(ray(&camera, u, 1.0).origin, origin);
+
+            // on the horizon they sit on the viewing circle, tangent to the ray
+            let r = ray(&camera, u, 0.5);
+            assert!(((r.origin - origin).length() - 0.032).abs() < 1e-5);
+            assert!(dot(r.origin - origin, r.direction).abs() < 1e-5);
+        }
+        // the eyes are on opposite sides for the same view direction
+        let (left, right) = (ray(&camera, 0.2, 0.5), ray(&camera, 0.7, 0.5));
+        assert_close(left.direction, right.direction);
+        assert_close(left.origin - origin, origin - right.origin);
     }
 }
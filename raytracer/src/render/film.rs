@@ -0,0 +1,649 @@
+use std::{
+    fs::{self, File},
+    io::{prelude::*, BufReader, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use super::{
+    aov::{Aov, PathSample},
+    filter::Filter,
+    sample_generator::SamplePattern,
+};
+use crate::utils::vector::*;
+
+/// Keeps relative error from blowing up in nearly black pixels.
+const DARK_LUMINANCE: f32 = 0.01;
+/// Identifies checkpoint files, and their layout version.
+const CHECKPOINT_MAGIC: &[u8; 8] = b"RTCKPT04";
+
+/// Sampling progress of one pixel, carried over between progressive passes.
+#[derive(Copy, Clone, Default)]
+pub struct PixelStats {
+    pub samples: u32,
+    /// Running mean and sum of squared deviations of the exposed luminance
+    /// (Welford), for adaptive sampling.
+    pub mean: f32,
+    pub m2: f32,
+    /// Set once adaptive sampling decides the pixel is done.
+    pub converged: bool,
+}
+
+impl PixelStats {
+    /// Standard error of the pixel's mean luminance, relative to the mean.
+    /// Needs at least two samples.
+    pub fn relative_error(&self) -> f32 {
+        // in 64 bits, as the product overflows past 65536 samples
+        let n = self.samples as u64;
+        let std_error = (self.m2 / (n * (n - 1)) as f32).sqrt();
+        std_error / (self.mean + DARK_LUMINANCE)
+    }
+}
+
+/// Accumulates filtered samples for the whole image. Pixel `(x, y)` covers
+/// film positions `[x, x + 1) x [y, y + 1)`.
+pub struct Film {
+    size: (u32, u32),
+    filter: Filter,
+    pixels: Vec<Color>,
+    weights: Vec<f32>,
+    stats: Vec<PixelStats>,
+    aovs: Vec<Aov>,
+    /// One buffer per AOV; see `Aov::is_light` for how each is accumulated.
+    aov_pixels: Vec<Vec<Color>>,
+}
+
+impl Film {
+    pub fn new(size: (u32, u32), filter: Filter, aovs: &[Aov]) -> Film {
+        let len = (size.0 * size.1) as usize;
+        Film {
+            size,
+            filter,
+            pixels: vec![Color::new(0.0, 0.0, 0.0); len],
+            weights: vec![0.0; len],
+            stats: vec![PixelStats::default(); len],
+            aovs: aovs.to_vec(),
+            aov_pixels: vec![vec![Color::new(0.0, 0.0, 0.0); len]; aovs.len()],
+        }
+    }
+
+    /// Empty tile for rendering the pixels from `min` up to, but excluding,
+    /// `max`. It also covers the neighbours that those pixels' samples
+    /// reach through the filter.
+    pub fn tile(&self, min: (u32, u32), max: (u32, u32)) -> FilmTile {
+        let reach = (self.filter.radius() - 0.5).ceil().max(0.0) as u32;
+        let (core_min, core_max) = (min, max);
+        let min = (min.0.saturating_sub(reach), min.1.saturating_sub(reach));
+        let max = (
+            (max.0 + reach).min(self.size.0),
+            (max.1 + reach).min(self.size.1),
+        );
+        let len = ((max.0 - min.0) * (max.1 - min.1)) as usize;
+        let mut tile = FilmTile {
+            min,
+            max,
+            core_min,
+            core_max,
+            filter: self.filter,
+            pixels: vec![Color::new(0.0, 0.0, 0.0); len],
+            weights: vec![0.0; len],
+            stats: vec![PixelStats::default(); len],
+            aovs: self.aovs.clone(),
+            aov_pixels: vec![vec![Color::new(0.0, 0.0, 0.0); len]; self.aovs.len()],
+        };
+        for y in core_min.1..core_max.1 {
+            for x in core_min.0..core_max.0 {
+                let (i, j) = (tile.index((x, y)), (x + y * self.size.0) as usize);
+                tile.stats[i] = self.stats[j];
+                for (a, aov) in self.aovs.iter().enumerate() {
+                    if !aov.is_light() {
+                        tile.aov_pixels[a][i] = self.aov_pixels[a][j];
+                    }
+                }
+            }
+        }
+        tile
+    }
+
+    /// Adds a finished tile's samples. Tiles have to be merged in the same
+    /// order every time for a render to be repeatable.
+    pub fn merge_tile(&mut self, tile: &FilmTile) {
+        for y in tile.min.1..tile.max.1 {
+            for x in tile.min.0..tile.max.0 {
+                let i = (x + y * self.size.0) as usize;
+                let j = tile.index((x, y));
+                self.pixels[i] += tile.pixels[j];
+                self.weights[i] += tile.weights[j];
+                for (a, aov) in self.aovs.iter().enumerate() {
+                    if aov.is_light() {
+                        self.aov_pixels[a][i] += tile.aov_pixels[a][j];
+                    }
+                }
+            }
+        }
+        for y in tile.core_min.1..tile.core_max.1 {
+            for x in tile.core_min.0..tile.core_max.0 {
+                let (i, j) = ((x + y * self.size.0) as usize, tile.index((x, y)));
+                self.stats[i] = tile.stats[j];
+                for (a, aov) in self.aovs.iter().enumerate() {
+                    if !aov.is_light() {
+                        self.aov_pixels[a][i] = tile.aov_pixels[a][j];
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reconstructed radiance of a pixel.
+    pub fn pixel(&self, pos: (u32, u32)) -> Color {
+        let i = (pos.0 + pos.1 * self.size.0) as usize;
+        if self.weights[i] == 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let c = self.pixels[i] / self.weights[i];
+        // negative filter lobes can overshoot below black
+        Color::new(c.x.max(0.0), c.y.max(0.0), c.z.max(0.0))
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    /// AOVs the film records.
+    pub fn aovs(&self) -> &[Aov] {
+        &self.aovs
+    }
+
+    /// Value of the `index`th AOV in a pixel.
+    pub fn aov(&self, index: usize, pos: (u32, u32)) -> Color {
+        let i = (pos.0 + pos.1 * self.size.0) as usize;
+        let aov = self.aovs[index];
+        let total = if aov.is_id() {
+            1.0
+        } else if aov.is_light() {
+            self.weights[i]
+        } else {
+            self.stats[i].samples as f32
+        };
+        if total == 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        self.aov_pixels[index][i] / total
+    }
+
+    /// Saves the film so an interrupted render can pick up from it. Along
+    /// with the film it stores the render seed, the sample pattern and the
+    /// number of samples per pixel it is laid out for, the number of samples
+    /// per pixel rendered so far, the filter and the AOVs.
+    ///
+    /// Layout, little endian: magic, width, height, seed, the pattern's name,
+    /// samples per pixel, sample limit, the filter's description, number of
+    /// AOVs and their names, then per pixel
+    /// the weighted color sum, the weight, its stats and its AOVs. Strings
+    /// are stored as their length followed by their bytes.
+    ///
+    /// The checkpoint is written next to `path` first and then moved over
+    /// it, so a crash while saving never loses the previous one.
+    pub fn write_checkpoint(
+        &self,
+        path: &Path,
+        seed: u32,
+        sequence: (SamplePattern, u32),
+        sample_limit: u32,
+    ) {
+        let display = path.display();
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+        let file = match File::create(&temp_path) {
+            Err(why) => panic!("couldn't create {}: {}", temp_path.display(), why),
+            Ok(file) => file,
+        };
+
+        let pixel_len = 29 + 12 * self.aovs.len();
+        let mut bytes = Vec::with_capacity(64 + self.pixels.len() * pixel_len);
+        bytes.extend_from_slice(CHECKPOINT_MAGIC);
+        for v in [self.size.0, self.size.1, seed] {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        write_string(&mut bytes, sequence.0.name());
+        for v in [sequence.1, sample_limit] {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        write_string(&mut bytes, &self.filter.describe());
+        bytes.extend_from_slice(&(self.aovs.len() as u32).to_le_bytes());
+        for aov in self.aovs.iter() {
+            write_string(&mut bytes, aov.name());
+        }
+        for i in 0..self.pixels.len() {
+            let (c, stats) = (self.pixels[i], self.stats[i]);
+            for v in [c.x, c.y, c.z, self.weights[i]] {
+                bytes.extend_from_slice(&v.to_le_bytes());
+            }
+            bytes.extend_from_slice(&stats.samples.to_le_bytes());
+            bytes.extend_from_slice(&stats.mean.to_le_bytes());
+            bytes.extend_from_slice(&stats.m2.to_le_bytes());
+            bytes.push(stats.converged as u8);
+            for aov_pixels in self.aov_pixels.iter() {
+                let c = aov_pixels[i];
+                for v in [c.x, c.y, c.z] {
+                    bytes.extend_from_slice(&v.to_le_bytes());
+                }
+            }
+        }
+
+        let mut writer = BufWriter::new(file);
+        let written = writer
+            .write_all(&bytes)
+            .and_then(|_| writer.flush())
+            .and_then(|_| writer.get_ref().sync_all());
+        if let Err(why) = written {
+            panic!("couldn't write to {}: {}", temp_path.display(), why);
+        }
+        match fs::rename(&temp_path, path) {
+            Err(why) => panic!(
+                "couldn't move {} to {}: {}",
+                temp_path.display(),
+                display,
+                why
+            ),
+            Ok(_) => println!("saved checkpoint to {}", display),
+        }
+    }
+
+    /// Loads a film saved by `write_checkpoint` and returns it with the
+    /// number of samples per pixel it holds. The checkpoint has to come from
+    /// a render with the same size, seed, sample pattern, samples per pixel,
+    /// filter and AOVs, or the result wouldn't match an uninterrupted render.
+    /// `sequence` is the sample pattern and the number of samples per pixel
+    /// it is laid out for.
+    pub fn read_checkpoint(
+        path: &Path,
+        size: (u32, u32),
+        filter: Filter,
+        aovs: &[Aov],
+        seed: u32,
+        sequence: (SamplePattern, u32),
+    ) -> (Film, u32) {
+        let display = path.display();
+        let mut reader = match File::open(path) {
+            Err(why) => panic!("couldn't open {}: {}", display, why),
+            Ok(file) => BufReader::new(file),
+        };
+
+        if &read_bytes::<8>(&mut reader, path) != CHECKPOINT_MAGIC {
+            panic!("{} is not a checkpoint", display);
+        }
+        let r = &mut reader;
+        let checkpoint_size = (read_u32(r, path), read_u32(r, path));
+        let checkpoint_seed = read_u32(r, path);
+        let checkpoint_pattern = read_string(r, path);
+        let checkpoint_samples = read_u32(r, path);
+        let sample_limit = read_u32(r, path);
+        if checkpoint_size != size || checkpoint_seed != seed {
+            panic!(
+                "checkpoint {} is for a {}x{} render with seed {}, not {}x{} with seed {}",
+                display,
+                checkpoint_size.0,
+                checkpoint_size.1,
+                checkpoint_seed,
+                size.0,
+                size.1,
+                seed
+            );
+        }
+        let (pattern, samples_per_pixel) = sequence;
+        if checkpoint_pattern != pattern.name() || checkpoint_samples != samples_per_pixel {
+            panic!(
+                "checkpoint {} is for {} samples with {} samples per pixel, not {} with {}",
+                display,
+                checkpoint_pattern,
+                checkpoint_samples,
+                pattern.name(),
+                samples_per_pixel
+            );
+        }
+        let checkpoint_filter = read_string(r, path);
+        if checkpoint_filter != filter.describe() {
+            panic!(
+                "checkpoint {} was rendered with the {} filter, not {}",
+                display,
+                checkpoint_filter,
+                filter.describe()
+            );
+        }
+        let aov_count = read_u32(r, path);
+        let checkpoint_aovs: Vec<String> = (0..aov_count).map(|_| read_string(r, path)).collect();
+        let aov_names: Vec<&str> = aovs.iter().map(|aov| aov.name()).collect();
+        if checkpoint_aovs != aov_names {
+            panic!(
+                "checkpoint {} has the AOVs [{}], not [{}]",
+                display,
+                checkpoint_aovs.join(", "),
+                aov_names.join(", ")
+            );
+        }
+
+        let mut film = Film::new(size, filter, aovs);
+        for i in 0..film.pixels.len() {
+            film.pixels[i] = Color::new(read_f32(r, path), read_f32(r, path), read_f32(r, path));
+            film.weights[i] = read_f32(r, path);
+            film.stats[i] = PixelStats {
+                samples: read_u32(r, path),
+                mean: read_f32(r, path),
+                m2: read_f32(r, path),
+                converged: read_bytes::<1>(r, path)[0] != 0,
+            };
+            for aov_pixels in film.aov_pixels.iter_mut() {
+                aov_pixels[i] = Color::new(read_f32(r, path), read_f32(r, path), read_f32(r, path));
+            }
+        }
+
+        (film, sample_limit)
+    }
+
+    /// Relative error averaged over all pixels with enough samples to tell.
+    pub fn mean_relative_error(&self) -> f32 {
+        let (sum, count) = self
+            .stats
+            .iter()
+            .filter(|stats| stats.samples >= 2)
+            .fold((0.0, 0), |(sum, count), stats| {
+                (sum + stats.relative_error(), count + 1)
+            });
+        if count == 0 {
+            return f32::INFINITY;
+        }
+        sum / count as f32
+    }
+
+    /// Number of samples taken in a pixel, wherever they were splatted.
+    pub fn sample_count(&self, pos: (u32, u32)) -> u32 {
+        self.stats[(pos.0 + pos.1 * self.size.0) as usize].samples
+    }
+
+    /// Variance of a pixel's mean exposed luminance. Pixels with fewer than
+    /// two samples count as uncertain as they are bright.
+    pub fn variance(&self, pos: (u32, u32)) -> f32 {
+        let stats = self.stats[(pos.0 + pos.1 * self.size.0) as usize];
+        let n = stats.samples as u64;
+        if n < 2 {
+            return (stats.mean + DARK_LUMINANCE).powi(2);
+        }
+        stats.m2 / (n * (n - 1)) as f32
+    }
+
+    /// Index of `aov` among the film's AOVs, if it collects it.
+    pub fn aov_index(&self, aov: Aov) -> Option<usize> {
+        self.aovs.iter().position(|a| *a == aov)
+    }
+}
+
+/// Part of the film that one thread splats samples into.
+pub struct FilmTile {
+    min: (u32, u32),
+    max: (u32, u32),
+    /// Pixels this tile renders, as opposed to those it only splats onto.
+    core_min: (u32, u32),
+    core_max: (u32, u32),
+    filter: Filter,
+    pixels: Vec<Color>,
+    weights: Vec<f32>,
+    stats: Vec<PixelStats>,
+    aovs: Vec<Aov>,
+    aov_pixels: Vec<Vec<Color>>,
+}
+
+impl FilmTile {
+    /// Splats a sample of pixel `pos`, taken at film position `p`, onto every
+    /// pixel in reach.
+    pub fn add_sample(&mut self, pos: (u32, u32), p: (f32, f32), sample: &PathSample) {
+        let radius = self.filter.radius();
+        let x0 = ((p.0 - 0.5 - radius).ceil().max(self.min.0 as f32)) as u32;
+        let y0 = ((p.1 - 0.5 - radius).ceil().max(self.min.1 as f32)) as u32;
+        let x1 = ((p.0 - 0.5 + radius).floor() + 1.0).min(self.max.0 as f32) as u32;
+        let y1 = ((p.1 - 0.5 + radius).floor() + 1.0).min(self.max.1 as f32) as u32;
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let weight = self
+                    .filter
+                    .evaluate(x as f32 + 0.5 - p.0, y as f32 + 0.5 - p.1);
+                if weight == 0.0 {
+                    continue;
+                }
+                let i = self.index((x, y));
+                self.pixels[i] += weight * sample.color;
+                self.weights[i] += weight;
+                for (aov, aov_pixels) in self.aovs.iter().zip(self.aov_pixels.iter_mut()) {
+                    if aov.is_light() {
+                        aov_pixels[i] += weight * aov.value(sample);
+                    }
+                }
+            }
+        }
+
+        let i = self.index(pos);
+        for (aov, aov_pixels) in self.aovs.iter().zip(self.aov_pixels.iter_mut()) {
+            if aov.is_feature() {
+                aov_pixels[i] += aov.value(sample);
+            }
+        }
+    }
+
+    /// Records the IDs a sample saw as those of the pixel it belongs to.
+    pub fn set_ids(&mut self, pos: (u32, u32), sample: &PathSample) {
+        let i = self.index(pos);
+        for (aov, aov_pixels) in self.aovs.iter().zip(self.aov_pixels.iter_mut()) {
+            if aov.is_id() {
+                aov_pixels[i] = aov.value(sample);
+            }
+        }
+    }
+
+    /// Sampling progress of one of the tile's own pixels.
+    pub fn stats_mut(&mut self, pos: (u32, u32)) -> &mut PixelStats {
+        let i = self.index(pos);
+        &mut self.stats[i]
+    }
+
+    fn index(&self, pos: (u32, u32)) -> usize {
+        ((pos.0 - self.min.0) + (pos.1 - self.min.1) * (self.max.0 - self.min.0)) as usize
+    }
+}
+
+fn read_bytes<const L: usize>(reader: &mut impl Read, path: &Path) -> [u8; L] {
+    let mut bytes = [0; L];
+    if let Err(why) = reader.read_exact(&mut bytes) {
+        panic!("couldn't read {}: {}", path.display(), why);
+    }
+    bytes
+}
+
+fn read_string(reader: &mut impl Read, path: &Path) -> String {
+    let len = read_u32(reader, path) as usize;
+    let mut bytes = vec![0; len];
+    if let Err(why) = reader.read_exact(&mut bytes) {
+        panic!("couldn't read {}: {}", path.display(), why);
+    }
+    match String::from_utf8(bytes) {
+        Err(why) => panic!("couldn't read {}: {}", path.display(), why),
+        Ok(s) => s,
+    }
+}
+
+fn write_string(bytes: &mut Vec<u8>, s: &str) {
+    bytes.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(s.as_bytes());
+}
+
+fn read_u32(reader: &mut impl Read, path: &Path) -> u32 {
+    u32::from_le_bytes(read_bytes(reader, path))
+}
+
+fn read_f32(reader: &mut impl Read, path: &Path) -> f32 {
+    f32::from_le_bytes(read_bytes(reader, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::{aov::FirstHit, materials::material::Lobe};
+    use std::env;
+
+    const SIZE: (u32, u32) = (6, 4);
+    const FILTER: Filter = Filter::Gaussian {
+        radius: 1.5,
+        sigma: 0.5,
+    };
+    const AOVS: [Aov; 2] = [Aov::Depth, Aov::DiffuseDirect];
+    const SEQUENCE: (SamplePattern, u32) = (SamplePattern::Sobol, 64);
+
+    /// A film with a couple of samples in every pixel.
+    fn rendered_film() -> Film {
+        let mut film = Film::new(SIZE, FILTER, &AOVS);
+        let mut tile = film.tile((0, 0), SIZE);
+        for y in 0..SIZE.1 {
+            for x in 0..SIZE.0 {
+                let sample = PathSample {
+                    first_hit: Some(FirstHit {
+                        dist: 1.0 + x as f32,
+                        normal: Vec3::new(0.0, 1.0, 0.0),
+                        albedo: Color::new(0.5, 0.5, 0.5),
+                        p: Point3::new(x as f32, y as f32, -1.0),
+                        material_id: 1,
+                        object_id: 2,
+                        lobe: Lobe::Diffuse,
+                    }),
+                    bounces: 1,
+                    color: Color::new(0.1 * x as f32, 0.2, 0.05 * y as f32),
+                };
+                tile.add_sample((x, y), (x as f32 + 0.25, y as f32 + 0.75), &sample);
+                *tile.stats_mut((x, y)) = PixelStats {
+                    samples: 2,
+                    mean: 0.3 + x as f32,
+                    m2: 0.01 * y as f32,
+                    converged: x == y,
+                };
+            }
+        }
+        film.merge_tile(&tile);
+        film
+    }
+
+    fn checkpoint_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("raytracer-{}-{}.bin", name, std::process::id()))
+    }
+
+    fn bits(c: Color) -> [u32; 3] {
+        [c.x.to_bits(), c.y.to_bits(), c.z.to_bits()]
+    }
+
+    #[test]
+    fn checkpoints_round_trip() {
+        let film = rendered_film();
+        let path = checkpoint_path("round-trip");
+        film.write_checkpoint(&path, 7, SEQUENCE, 64);
+        let (read, sample_limit) = Film::read_checkpoint(&path, SIZE, FILTER, &AOVS, 7, SEQUENCE);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(sample_limit, 64);
+        for i in 0..film.pixels.len() {
+            assert_eq!(bits(read.pixels[i]), bits(film.pixels[i]));
+            assert_eq!(read.weights[i].to_bits(), film.weights[i].to_bits());
+            let (a, b) = (read.stats[i], film.stats[i]);
+            assert_eq!(
+                (a.samples, a.mean, a.m2, a.converged),
+                (b.samples, b.mean, b.m2, b.converged)
+            );
+            for a in 0..AOVS.len() {
+                assert_eq!(bits(read.aov_pixels[a][i]), bits(film.aov_pixels[a][i]));
+            }
+        }
+    }
+
+    #[test]
+    fn saving_leaves_no_temporary_file() {
+        let path = checkpoint_path("temporary");
+        rendered_film().write_checkpoint(&path, 0, SEQUENCE, 1);
+        rendered_film().write_checkpoint(&path, 0, SEQUENCE, 2);
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        assert!(!Path::new(&temp_path).exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "filter")]
+    fn checkpoints_from_another_filter_are_refused() {
+        let path = checkpoint_path("filter");
+        rendered_film().write_checkpoint(&path, 0, SEQUENCE, 1);
+        let box_filter = Filter::Box { radius: 0.5 };
+        let result = std::panic::catch_unwind(|| {
+            Film::read_checkpoint(&path, SIZE, box_filter, &AOVS, 0, SEQUENCE);
+        });
+        fs::remove_file(&path).unwrap();
+        std::panic::resume_unwind(result.unwrap_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "AOVs")]
+    fn checkpoints_with_other_aovs_are_refused() {
+        let path = checkpoint_path("aovs");
+        rendered_film().write_checkpoint(&path, 0, SEQUENCE, 1);
+        let swapped = [Aov::DiffuseDirect, Aov::Depth];
+        let result = std::panic::catch_unwind(|| {
+            Film::read_checkpoint(&path, SIZE, FILTER, &swapped, 0, SEQUENCE);
+        });
+        fs::remove_file(&path).unwrap();
+        std::panic::resume_unwind(result.unwrap_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "samples per pixel")]
+    fn checkpoints_for_another_sample_count_are_refused() {
+        let path = checkpoint_path("samples");
+        rendered_film().write_checkpoint(&path, 0, SEQUENCE, 1);
+        let result = std::panic::catch_unwind(|| {
+            Film::read_checkpoint(&path, SIZE, FILTER, &AOVS, 0, (SamplePattern::Sobol, 128));
+        });
+        fs::remove_file(&path).unwrap();
+        std::panic::resume_unwind(result.unwrap_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "stratified")]
+    fn checkpoints_for_another_sample_pattern_are_refused() {
+        let path = checkpoint_path("pattern");
+        rendered_film().write_checkpoint(&path, 0, (SamplePattern::Stratified, 64), 1);
+        let result = std::panic::catch_unwind(|| {
+            Film::read_checkpoint(&path, SIZE, FILTER, &AOVS, 0, SEQUENCE);
+        });
+        fs::remove_file(&path).unwrap();
+        std::panic::resume_unwind(result.unwrap_err());
+    }
+
+    #[test]
+    fn relative_error_holds_for_many_samples() {
+        let stats = PixelStats {
+            samples: 100_000,
+            mean: 1.0 - DARK_LUMINANCE,
+            m2: 1e10,
+            converged: false,
+        };
+        let expected = (1e10 / (100_000.0 * 99_999.0) as f32).sqrt();
+        assert!((stats.relative_error() - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn variance_holds_for_many_samples() {
+        let mut film = Film::new((1, 1), FILTER, &[]);
+        film.stats[0] = PixelStats {
+            samples: 100_000,
+            mean: 0.5,
+            m2: 1e10,
+            converged: false,
+        };
+        let expected = 1e10 / (100_000.0 * 99_999.0) as f32;
+        assert!((film.variance((0, 0)) - expected).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,180 @@
+const PI: f32 = std::f32::consts::PI;
+
+/// * Box { radius: f32 }
+/// * Gaussian { radius: f32, sigma: f32 }
+/// * Mitchell { radius: f32, b: f32, c: f32 }
+/// * BlackmanHarris { radius: f32 }
+/// * Lanczos { radius: f32, tau: f32 }
+///
+/// Pixel reconstruction filter. Every sample is splatted onto all pixels
+/// whose centers lie within `radius` of it, in pixels, weighted by the
+/// filter. All filters are separable.
+#[allow(dead_code)]
+#[derive(Copy, Clone)]
+pub enum Filter {
+    /// A radius of 0.5 averages the samples inside each pixel.
+    Box { radius: f32 },
+    /// Gaussian shifted down so it reaches zero at the radius.
+    Gaussian { radius: f32, sigma: f32 },
+    /// Mitchell-Netravali cubic. B = C = 1/3 is the recommended tradeoff
+    /// between blurring and ringing.
+    Mitchell { radius: f32, b: f32, c: f32 },
+    /// Four-term Blackman-Harris window.
+    BlackmanHarris { radius: f32 },
+    /// Sinc windowed by a sinc stretched `tau` times wider.
+    Lanczos { radius: f32, tau: f32 },
+}
+
+impl Filter {
+    pub fn radius(&self) -> f32 {
+        match *self {
+            Filter::Box { radius } => radius,
+            Filter::Gaussian { radius, .. } => radius,
+            Filter::Mitchell { radius, .. } => radius,
+            Filter::BlackmanHarris { radius } => radius,
+            Filter::Lanczos { radius, .. } => radius,
+        }
+    }
+
+    /// Name and parameters, which tell filters apart.
+    pub fn describe(&self) -> String {
+        match *self {
+            Filter::Box { radius } => format!("box, radius {}", radius),
+            Filter::Gaussian { radius, sigma } => {
+                format!("gaussian, radius {}, sigma {}", radius, sigma)
+            }
+            Filter::Mitchell { radius, b, c } => {
+                format!("mitchell, radius {}, b {}, c {}", radius, b, c)
+            }
+            Filter::BlackmanHarris { radius } => format!("blackman-harris, radius {}", radius),
+            Filter::Lanczos { radius, tau } => format!("lanczos, radius {}, tau {}", radius, tau),
+        }
+    }
+
+    /// Weight of a sample at offset `(x, y)` pixels from a pixel's center.
+    pub fn evaluate(&self, x: f32, y: f32) -> f32 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        let radius = self.radius();
+        let x = x.abs();
+        if x >= radius {
+            return 0.0;
+        }
+        match *self {
+            Filter::Box { .. } => 1.0,
+            Filter::Gaussian { sigma, .. } => {
+                (gaussian(x, sigma) - gaussian(radius, sigma)).max(0.0)
+            }
+            Filter::Mitchell { b, c, .. } => mitchell(2.0 * x / radius, b, c),
+            Filter::BlackmanHarris { .. } => {
+                let t = 2.0 * PI * (x / radius + 1.0) / 2.0;
+                0.35875 - 0.48829 * t.cos() + 0.14128 * (2.0 * t).cos() - 0.01168 * (3.0 * t).cos()
+            }
+            Filter::Lanczos { tau, .. } => sinc(x) * sinc(x / tau),
+        }
+    }
+}
+
+fn gaussian(x: f32, sigma: f32) -> f32 {
+    (-x * x / (2.0 * sigma * sigma)).exp() / (2.0 * PI * sigma * sigma).sqrt()
+}
+
+/// Mitchell-Netravali cubic over `[0, 2)`.
+fn mitchell(x: f32, b: f32, c: f32) -> f32 {
+    if x > 1.0 {
+        ((-b - 6.0 * c) * x * x * x
+            + (6.0 * b + 30.0 * c) * x * x
+            + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c))
+            / 6.0
+    } else {
+        ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+            + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+            + (6.0 - 2.0 * b))
+            / 6.0
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x < 1e-5 {
+        return 1.0;
+    }
+    (PI * x).sin() / (PI * x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        render::{aov::PathSample, film::Film},
+        utils::vector::Color,
+    };
+
+    const FILTERS: [Filter; 5] = [
+        Filter::Box { radius: 0.5 },
+        Filter::Gaussian {
+            radius: 1.5,
+            sigma: 0.5,
+        },
+        Filter::Mitchell {
+            radius: 2.0,
+            b: 1.0 / 3.0,
+            c: 1.0 / 3.0,
+        },
+        Filter::BlackmanHarris { radius: 2.0 },
+        Filter::Lanczos {
+            radius: 3.0,
+            tau: 3.0,
+        },
+    ];
+
+    #[test]
+    fn filters_vanish_at_the_radius_and_are_symmetric() {
+        for filter in FILTERS {
+            let radius = filter.radius();
+            assert_eq!(filter.evaluate(radius, 0.0), 0.0, "{}", filter.describe());
+            assert_eq!(filter.evaluate(0.0, -radius), 0.0, "{}", filter.describe());
+            assert!(filter.evaluate(0.0, 0.0) > 0.0, "{}", filter.describe());
+            for &x in &[0.1, 0.3, 0.49, 1.2, 2.7] {
+                assert_eq!(filter.evaluate(x, 0.2), filter.evaluate(-x, -0.2));
+            }
+        }
+    }
+
+    #[test]
+    fn weights_normalize_a_constant_image() {
+        let size = (8, 8);
+        let color = Color::new(0.25, 0.5, 2.0);
+        let sample = PathSample {
+            first_hit: None,
+            bounces: 0,
+            color,
+        };
+        for filter in FILTERS {
+            let mut film = Film::new(size, filter, &[]);
+            let mut tile = film.tile((0, 0), size);
+            for y in 0..size.1 {
+                for x in 0..size.0 {
+                    for (u, v) in [(0.2, 0.3), (0.7, 0.1), (0.4, 0.8), (0.9, 0.6)] {
+                        tile.add_sample((x, y), (x as f32 + u, y as f32 + v), &sample);
+                    }
+                }
+            }
+            film.merge_tile(&tile);
+
+            for y in 0..size.1 {
+                for x in 0..size.0 {
+                    let c = film.pixel((x, y));
+                    assert!(
+                        (c - color).length() < 1e-5,
+                        "{} at {:?}",
+                        filter.describe(),
+                        (x, y)
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,441 @@
+use std::fs;
+
+use super::sample_generator::SampleGenerator;
+use crate::utils::vector::*;
+
+/// One refracting surface of a lens prescription, in meters. A zero
+/// `curvature_radius` marks the aperture stop, and a zero `ior` means air.
+#[derive(Copy, Clone)]
+pub struct LensElement {
+    pub curvature_radius: f32,
+    /// Distance along the axis to the next surface towards the film.
+    pub thickness: f32,
+    pub ior: f32,
+    pub aperture_radius: f32,
+}
+
+/// A real multi-element lens, traced surface by surface like pbrt's
+/// RealisticCamera. Camera space has the film at z = 0 and the lens towards
+/// +z; internally the elements sit at negative z, front element first.
+pub struct LensSystem {
+    elements: Vec<LensElement>,
+    /// Stop radius from the prescription, before any f-number is applied.
+    max_stop_radius: f32,
+    /// Fraction of rays aimed at the rear element from the center of the film
+    /// that make it through, used to normalize brightness.
+    center_transmission: f32,
+}
+
+impl LensSystem {
+    pub fn new(elements: Vec<LensElement>) -> LensSystem {
+        let max_stop_radius = match elements.iter().find(|e| e.curvature_radius == 0.0) {
+            Some(stop) => stop.aperture_radius,
+            None => panic!("lens prescription has no aperture stop"),
+        };
+        LensSystem {
+            elements,
+            max_stop_radius,
+            center_transmission: 1.0,
+        }
+    }
+
+    /// Loads a prescription in pbrt's format: one surface per line, front to
+    /// back, with curvature radius, thickness, index of refraction and
+    /// aperture diameter, all in millimeters. Lines starting with `#` are
+    /// comments.
+    #[allow(dead_code)]
+    pub fn from_file(file_path: &str) -> LensSystem {
+        let text = match fs::read_to_string(file_path) {
+            Err(why) => panic!("couldn't read {}: {}", file_path, why),
+            Ok(text) => text,
+        };
+
+        let mut elements = Vec::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let values: Vec<f32> = line
+                .split_whitespace()
+                .map(|v| match v.parse() {
+                    Err(why) => panic!("bad value {:?} in {}: {}", v, file_path, why),
+                    Ok(v) => v,
+                })
+                .collect();
+            if values.len() != 4 {
+                panic!("expected 4 values per surface in {}: {:?}", file_path, line);
+            }
+            elements.push(LensElement {
+                curvature_radius: values[0] * 0.001,
+                thickness: values[1] * 0.001,
+                ior: values[2],
+                aperture_radius: values[3] * 0.001 / 2.0,
+            });
+        }
+
+        LensSystem::new(elements)
+    }
+
+    /// Stops the lens down to `f_stop`, moves the film so objects at
+    /// `focus_dist` from it are sharp, and recalibrates the brightness.
+    pub fn focus(&mut self, focus_dist: f32, f_stop: f32) {
+        let (pz, fz) = self.thick_lens_approximation();
+        let focal_length = fz[0] - pz[0];
+
+        let stop = self.stop_index();
+        self.elements[stop].aperture_radius =
+            (focal_length.abs() / (2.0 * f_stop)).min(self.max_stop_radius);
+
+        let z = -focus_dist;
+        let c = (pz[1] - z - pz[0]) * (pz[1] - z - 4.0 * focal_length - pz[0]);
+        if c <= 0.0 {
+            panic!(
+                "can't focus at {}; it is too close for this lens",
+                focus_dist
+            );
+        }
+        let delta = 0.5 * (pz[1] - z + pz[0] - c.sqrt());
+        let last = self.elements.len() - 1;
+        self.elements[last].thickness += delta;
+
+        self.center_transmission = self.measure_center_transmission();
+    }
+
+    /// Traces a ray from a point on the film towards a random point on the
+    /// rear element. Returns the ray leaving the front of the lens in camera
+    /// space and its weight, or `None` if the glass or stops block it.
+    pub fn sample_ray(&self, film: Vec3, gen: &mut SampleGenerator) -> Option<(Vec3, Vec3, f32)> {
+        let rear = self.rear_point(gen.in_unit_disk());
+        let direction = (rear - film).unit();
+        let (origin, out_direction) = self.trace_from_film(film, direction)?;
+
+        // radiometric falloff towards the edge of the frame
+        let cos_theta = direction.z;
+        let weight = cos_theta.powi(4) / self.center_transmission;
+        Some((origin, out_direction, weight))
+    }
+
+    fn stop_index(&self) -> usize {
+        self.elements
+            .iter()
+            .position(|e| e.curvature_radius == 0.0)
+            .unwrap()
+    }
+
+    fn rear_z(&self) -> f32 {
+        self.elements.last().unwrap().thickness
+    }
+
+    fn front_z(&self) -> f32 {
+        self.elements.iter().map(|e| e.thickness).sum()
+    }
+
+    fn rear_point(&self, disk: Vec3) -> Vec3 {
+        let radius = self.elements.last().unwrap().aperture_radius;
+        Vec3::new(radius * disk.x, radius * disk.y, self.rear_z())
+    }
+
+    fn measure_center_transmission(&self) -> f32 {
+        // deterministic grid over the rear element
+        let n = 64;
+        let (mut total, mut passed) = (0, 0);
+        for i in 0..n {
+            for j in 0..n {
+                let x = 2.0 * (i as f32 + 0.5) / n as f32 - 1.0;
+                let y = 2.0 * (j as f32 + 0.5) / n as f32 - 1.0;
+                if x * x + y * y > 1.0 {
+                    continue;
+                }
+                total += 1;
+                let film = Vec3::new(0.0, 0.0, 0.0);
+                let rear = self.rear_point(Vec3::new(x, y, 0.0));
+                if self.trace_from_film(film, (rear - film).unit()).is_some() {
+                    passed += 1;
+                }
+            }
+        }
+        if passed == 0 {
+            panic!("no light makes it through the lens");
+        }
+        passed as f32 / total as f32
+    }
+
+    /// Follows a camera-space ray from the film out through the front element.
+    fn trace_from_film(&self, origin: Vec3, direction: Vec3) -> Option<(Vec3, Vec3)> {
+        let mut o = flip_z(origin);
+        let mut d = flip_z(direction);
+        let mut element_z = 0.0;
+
+        for (i, element) in self.elements.iter().enumerate().rev() {
+            element_z -= element.thickness;
+            let (o_next, d_next) = trace_surface(element, element_z, o, d, || {
+                let eta_i = element.ior;
+                let eta_t = match i {
+                    0 => 1.0,
+                    _ => air_or(self.elements[i - 1].ior),
+                };
+                eta_i / eta_t
+            })?;
+            o = o_next;
+            d = d_next;
+        }
+
+        Some((flip_z(o), flip_z(d)))
+    }
+
+    /// Follows a camera-space ray from the scene in through the lens to the film.
+    fn trace_from_scene(&self, origin: Vec3, direction: Vec3) -> Option<(Vec3, Vec3)> {
+        let mut o = flip_z(origin);
+        let mut d = flip_z(direction);
+        let mut element_z = -self.front_z();
+
+        for (i, element) in self.elements.iter().enumerate() {
+            let (o_next, d_next) = trace_surface(element, element_z, o, d, || {
+                let eta_i = match i {
+                    0 => 1.0,
+                    _ => air_or(self.elements[i - 1].ior),
+                };
+                eta_i / air_or(element.ior)
+            })?;
+            o = o_next;
+            d = d_next;
+            element_z += element.thickness;
+        }
+
+        Some((flip_z(o), flip_z(d)))
+    }
+
+    /// Principal plane and focal point positions of the equivalent thick lens,
+    /// measured from both sides.
+    fn thick_lens_approximation(&self) -> ([f32; 2], [f32; 2]) {
+        // a ray parallel to the axis, just off it
+        let x = 0.001 * SENSOR_DIAGONAL;
+
+        let scene_ray = (
+            Vec3::new(x, 0.0, self.front_z() + 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+        );
+        let film_ray = match self.trace_from_scene(scene_ray.0, scene_ray.1) {
+            None => panic!("paraxial ray from the scene didn't make it through the lens"),
+            Some(r) => r,
+        };
+        let (pz0, fz0) = cardinal_points(scene_ray, film_ray);
+
+        let film_ray = (
+            Vec3::new(x, 0.0, self.rear_z() - 1.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        );
+        let scene_ray = match self.trace_from_film(film_ray.0, film_ray.1) {
+            None => panic!("paraxial ray from the film didn't make it through the lens"),
+            Some(r) => r,
+        };
+        let (pz1, fz1) = cardinal_points(film_ray, scene_ray);
+
+        ([pz0, pz1], [fz0, fz1])
+    }
+}
+
+/// Diagonal of a 35mm full-frame sensor in meters.
+const SENSOR_DIAGONAL: f32 = 0.0433;
+
+fn air_or(ior: f32) -> f32 {
+    if ior == 0.0 {
+        1.0
+    } else {
+        ior
+    }
+}
+
+fn flip_z(v: Vec3) -> Vec3 {
+    Vec3::new(v.x, v.y, -v.z)
+}
+
+fn cardinal_points(r_in: (Vec3, Vec3), r_out: (Vec3, Vec3)) -> (f32, f32) {
+    let (o_in, _) = r_in;
+    let (o, d) = r_out;
+    let tf = -o.x / d.x;
+    let fz = -(o + tf * d).z;
+    let tp = (o_in.x - o.x) / d.x;
+    let pz = -(o + tp * d).z;
+    (pz, fz)
+}
+
+/// Intersects a lens-space ray with one surface, checks its aperture and
+/// refracts it with the relative index from `eta`.
+fn trace_surface(
+    element: &LensElement,
+    element_z: f32,
+    o: Vec3,
+    d: Vec3,
+    eta: impl Fn() -> f32,
+) -> Option<(Vec3, Vec3)> {
+    let is_stop = element.curvature_radius == 0.0;
+    let (t, normal) = if is_stop {
+        if d.z == 0.0 {
+            return None;
+        }
+        ((element_z - o.z) / d.z, Vec3::new(0.0, 0.0, 0.0))
+    } else {
+        let radius = element.curvature_radius;
+        intersect_spherical_element(radius, element_z + radius, o, d)?
+    };
+    if t < 0.0 {
+        return None;
+    }
+
+    let hit = o + t * d;
+    if hit.x * hit.x + hit.y * hit.y > element.aperture_radius * element.aperture_radius {
+        return None;
+    }
+
+    if is_stop {
+        return Some((hit, d));
+    }
+    let refracted = refract(-d.unit(), normal, eta())?;
+    Some((hit, refracted))
+}
+
+fn intersect_spherical_element(
+    radius: f32,
+    z_center: f32,
+    o: Vec3,
+    d: Vec3,
+) -> Option<(f32, Vec3)> {
+    let oc = o - Vec3::new(0.0, 0.0, z_center);
+    let a = d.length_squared();
+    let b = 2.0 * dot(d, oc);
+    let c = oc.length_squared() - radius * radius;
+
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let sqrt_d = discriminant.sqrt();
+    let t0 = (-b - sqrt_d) / (2.0 * a);
+    let t1 = (-b + sqrt_d) / (2.0 * a);
+
+    // which of the two intersections lies on the lens depends on whether the
+    // surface bulges towards the ray
+    let use_closer = (d.z > 0.0) != (radius < 0.0);
+    let t = if use_closer { t0.min(t1) } else { t0.max(t1) };
+    if t < 0.0 {
+        return None;
+    }
+
+    let mut normal = (oc + t * d).unit();
+    if dot(normal, d) > 0.0 {
+        normal = -normal;
+    }
+    Some((t, normal))
+}
+
+fn refract(wi: Vec3, n: Vec3, eta: f32) -> Option<Vec3> {
+    let cos_theta_i = dot(n, wi);
+    let sin2_theta_i = (1.0 - cos_theta_i * cos_theta_i).max(0.0);
+    let sin2_theta_t = eta * eta * sin2_theta_i;
+    if sin2_theta_t >= 1.0 {
+        return None;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    Some(eta * -wi + (eta * cos_theta_i - cos_theta_t) * n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::sample_generator::SamplePattern;
+
+    /// Paraxial focal length and back focal distance of the prescription in
+    /// meters, from a y-nu trace of its surfaces in double precision.
+    const DGAUSS_FOCAL_LENGTH: f32 = 0.050358;
+    const DGAUSS_BACK_FOCUS: f32 = 0.036106;
+
+    fn dgauss() -> LensSystem {
+        LensSystem::from_file("lenses/dgauss.50mm.dat")
+    }
+
+    #[test]
+    fn loads_the_prescription_in_meters() {
+        let lens = dgauss();
+        assert_eq!(lens.elements.len(), 11);
+        assert_eq!(lens.stop_index(), 5);
+        assert!((lens.max_stop_radius - 0.00855).abs() < 1e-7);
+        assert!((lens.front_z() - 0.03204).abs() < 1e-6);
+    }
+
+    #[test]
+    fn focal_length_and_back_focus_match_the_paraxial_values() {
+        let lens = dgauss();
+        let (pz, fz) = lens.thick_lens_approximation();
+        let focal_length = fz[0] - pz[0];
+        assert!(
+            (focal_length.abs() - DGAUSS_FOCAL_LENGTH).abs() < 1e-5,
+            "focal length {}",
+            focal_length
+        );
+        // the prescription ends at the last surface, at z = 0
+        let back_focus = (fz[0] - lens.rear_z()).abs();
+        assert!(
+            (back_focus - DGAUSS_BACK_FOCUS).abs() < 1e-5,
+            "back focus {}",
+            back_focus
+        );
+    }
+
+    #[test]
+    fn focusing_images_the_focus_distance_onto_the_film() {
+        let mut lens = dgauss();
+        let focus_dist = 2.0;
+        lens.focus(focus_dist, 8.0);
+        assert!(lens.center_transmission > 0.0 && lens.center_transmission <= 1.0);
+
+        // rays from an on-axis point at the focus distance, through a small
+        // zone of the front element, should cross the axis at the film
+        let point = Vec3::new(0.0, 0.0, focus_dist);
+        for height in [0.0005, 0.001] {
+            let front = Vec3::new(height, 0.0, lens.front_z());
+            let (o, d) = match lens.trace_from_scene(point, (front - point).unit()) {
+                Some(r) => r,
+                None => panic!("ray at height {} was blocked", height),
+            };
+            let axis_z = o.z - o.x / d.x * d.z;
+            assert!(
+                axis_z.abs() < 1e-5,
+                "ray at height {} crosses the axis at z = {}",
+                height,
+                axis_z
+            );
+        }
+    }
+
+    #[test]
+    fn stopping_down_narrows_the_stop() {
+        let mut lens = dgauss();
+        lens.focus(10.0, 1.0);
+        let wide_open = lens.elements[lens.stop_index()].aperture_radius;
+        assert_eq!(wide_open, lens.max_stop_radius);
+
+        lens.focus(10.0, 8.0);
+        let stopped_down = lens.elements[lens.stop_index()].aperture_radius;
+        assert!((stopped_down - DGAUSS_FOCAL_LENGTH / 16.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn rays_from_the_film_center_leave_towards_the_scene() {
+        let mut lens = dgauss();
+        lens.focus(5.0, 2.0);
+        let mut gen = SampleGenerator::new(SamplePattern::Independent, (0, 0), 64, 0);
+        let mut passed = 0;
+        for i in 0..64 {
+            gen.start_sample(i);
+            if let Some((origin, direction, weight)) =
+                lens.sample_ray(Vec3::new(0.0, 0.0, 0.0), &mut gen)
+            {
+                passed += 1;
+                assert!(origin.z > 0.0 && direction.z > 0.0);
+                assert!(weight > 0.0 && weight.is_finite());
+            }
+        }
+        assert!(passed > 0);
+    }
+}
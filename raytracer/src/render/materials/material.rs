@@ -1,12 +1,37 @@
 use crate::{
-    render::shapes::hit::HitData,
+    render::{
+        sample_generator::{hash, hash_combine, SampleGenerator},
+        shapes::hit::HitData,
+    },
     utils::{ray::*, vector::*},
 };
 
-use rand::prelude::*;
-
 pub trait Scatterer {
-    fn scatter(&self, ray_in: &Ray, hit_data: &HitData) -> (Ray, Color);
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        hit_data: &HitData,
+        gen: &mut SampleGenerator,
+    ) -> (Ray, Color, Lobe);
+}
+
+/// * Diffuse
+/// * Glossy
+/// * Transmission
+/// * Volume
+///
+/// Kind of bounce a scattered ray took, so each kind can have its own depth
+/// limit.
+#[allow(dead_code)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum Lobe {
+    Diffuse,
+    /// Reflection off a mirror or rough metal, or off the outside of glass.
+    Glossy,
+    /// Refraction through glass.
+    Transmission,
+    /// Scattering inside participating media.
+    Volume,
 }
 
 /// * Lambertian(albedo: Color)
@@ -19,37 +44,78 @@ pub enum Material {
     Dielectric { albedo: Color, ior: f32 },
 }
 
+impl Material {
+    pub fn albedo(&self) -> Color {
+        match self {
+            Material::Lambertian { albedo } => *albedo,
+            Material::Metal { albedo, .. } => *albedo,
+            Material::Dielectric { albedo, .. } => *albedo,
+        }
+    }
+
+    /// Hash of the material's kind and parameters, so identical materials
+    /// share an ID across renders.
+    pub fn id(&self) -> u32 {
+        let (kind, albedo, parameter) = match self {
+            Material::Lambertian { albedo } => (0, albedo, 0.0),
+            Material::Metal { albedo, fuzz } => (1, albedo, *fuzz),
+            Material::Dielectric { albedo, ior } => (2, albedo, *ior),
+        };
+        [albedo.x, albedo.y, albedo.z, parameter]
+            .iter()
+            .fold(hash(kind), |id, v| hash_combine(id, v.to_bits()))
+    }
+}
+
 impl Scatterer for Material {
-    fn scatter(&self, ray_in: &Ray, hit_data: &HitData) -> (Ray, Color) {
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        hit_data: &HitData,
+        gen: &mut SampleGenerator,
+    ) -> (Ray, Color, Lobe) {
         match self {
-            Material::Lambertian { albedo } => lambertian(*albedo, ray_in, hit_data),
-            Material::Metal { albedo, fuzz } => metal(*albedo, *fuzz, ray_in, hit_data),
-            Material::Dielectric { albedo, ior } => dielectric(*albedo, *ior, ray_in, hit_data),
+            Material::Lambertian { albedo } => lambertian(*albedo, ray_in, hit_data, gen),
+            Material::Metal { albedo, fuzz } => metal(*albedo, *fuzz, ray_in, hit_data, gen),
+            Material::Dielectric { albedo, ior } => {
+                dielectric(*albedo, *ior, ray_in, hit_data, gen)
+            }
         }
     }
 }
 
-fn lambertian(albedo: Color, ray_in: &Ray, hit_data: &HitData) -> (Ray, Color) {
-    let mut scatter_direction = hit_data.normal + random_in_unit_sphere();
+fn lambertian(
+    albedo: Color,
+    ray_in: &Ray,
+    hit_data: &HitData,
+    gen: &mut SampleGenerator,
+) -> (Ray, Color, Lobe) {
+    let mut scatter_direction = hit_data.normal + gen.in_unit_sphere();
 
     if scatter_direction.near_zero() {
         scatter_direction = hit_data.normal;
     }
 
-    let scattered = Ray::new(hit_data.p, scatter_direction);
-    return (scattered, albedo);
+    let scattered = Ray::new(hit_data.p, scatter_direction, ray_in.time);
+    return (scattered, albedo, Lobe::Diffuse);
 }
 
-fn metal(albedo: Color, fuzz: f32, ray_in: &Ray, hit_data: &HitData) -> (Ray, Color) {
+fn metal(
+    albedo: Color,
+    fuzz: f32,
+    ray_in: &Ray,
+    hit_data: &HitData,
+    gen: &mut SampleGenerator,
+) -> (Ray, Color, Lobe) {
     let reflected = reflect(ray_in.direction, hit_data.normal);
 
     let direction = if fuzz == 0.0 {
         reflected
     } else {
-        reflected + fuzz * random_in_unit_sphere()
+        reflected + fuzz * gen.in_unit_sphere()
     };
 
-    let scattered = Ray::new(hit_data.p, direction);
+    let scattered = Ray::new(hit_data.p, direction, ray_in.time);
 
     let attenuation = if dot(scattered.direction, hit_data.normal) <= 0.0 {
         Color::new(0.0, 0.0, 0.0)
@@ -57,10 +123,16 @@ fn metal(albedo: Color, fuzz: f32, ray_in: &Ray, hit_data: &HitData) -> (Ray, Co
         albedo
     };
 
-    return (scattered, attenuation);
+    return (scattered, attenuation, Lobe::Glossy);
 }
 
-fn dielectric(albedo: Color, ior: f32, ray_in: &Ray, hit_data: &HitData) -> (Ray, Color) {
+fn dielectric(
+    albedo: Color,
+    ior: f32,
+    ray_in: &Ray,
+    hit_data: &HitData,
+    gen: &mut SampleGenerator,
+) -> (Ray, Color, Lobe) {
     let refraction_ratio = if hit_data.front_face == true {
         1.0 / ior
     } else {
@@ -73,30 +145,23 @@ fn dielectric(albedo: Color, ior: f32, ray_in: &Ray, hit_data: &HitData) -> (Ray
 
     let cannot_refract = refraction_ratio * sin_theta > 1.0;
 
-    let mut attenuation = Color::new(1.0, 1.0, 1.0);
-    let direction = if cannot_refract || shlick_reflectance(cos_theta, ior) > random() {
-        reflect(unit_direction, hit_data.normal)
-    } else {
-        attenuation = albedo;
-        refract(unit_direction, hit_data.normal, refraction_ratio)
-    };
-
-    return (Ray::new(hit_data.p, direction), attenuation);
-}
-
-fn random_in_unit_sphere() -> Point3 {
-    let mut rng = rand::thread_rng();
-    loop {
-        let p = Vec3::new(
-            rng.gen_range(-1.0, 1.0),
-            rng.gen_range(-1.0, 1.0),
-            rng.gen_range(-1.0, 1.0),
-        );
-        if p.length() >= 1.0 {
-            continue;
-        }
-        return p;
-    }
+    let (direction, attenuation, lobe) =
+        if cannot_refract || shlick_reflectance(cos_theta, ior) > gen.get_1d() {
+            (
+                reflect(unit_direction, hit_data.normal),
+                Color::new(1.0, 1.0, 1.0),
+                Lobe::Glossy,
+            )
+        } else {
+            (
+                refract(unit_direction, hit_data.normal, refraction_ratio),
+                albedo,
+                Lobe::Transmission,
+            )
+        };
+
+    let scattered = Ray::new(hit_data.p, direction, ray_in.time);
+    return (scattered, attenuation, lobe);
 }
 
 fn reflect(v: Vec3, n: Vec3) -> Vec3 {
@@ -1,5 +1,13 @@
 pub mod sampler;
 pub mod scene;
+pub mod aov;
 pub mod camera;
+pub mod film;
+pub mod filter;
+pub mod lens;
 pub mod materials;
+pub mod renderer;
+pub mod sample_generator;
 pub mod shapes;
+pub mod stats;
+pub mod tiles;
@@ -0,0 +1,235 @@
+use rayon::iter::{ParallelBridge, ParallelIterator};
+use std::{fmt, sync::Mutex, time::Instant};
+
+use super::{
+    film::{Film, FilmTile},
+    sampler::Sampler,
+    stats, tiles,
+};
+use crate::scenes::RenderSettings;
+
+/// Pass size when stopping criteria, rather than progressive previews, call
+/// for rendering in passes.
+const DEFAULT_SAMPLES_PER_PASS: u32 = 16;
+
+/// Finished tiles waiting to be merged into the film. Tiles are merged
+/// strictly in render order, so the floating point sums, and with them the
+/// image, don't depend on which thread finished first.
+struct TileQueue<'a> {
+    film: &'a mut Film,
+    pending: Vec<Option<FilmTile>>,
+    next: usize,
+}
+
+impl TileQueue<'_> {
+    fn finish(&mut self, index: usize, tile: FilmTile) {
+        self.pending[index] = Some(tile);
+        while let Some(Some(tile)) = self.pending.get_mut(self.next).map(Option::take) {
+            self.film.merge_tile(&tile);
+            self.next += 1;
+        }
+    }
+}
+
+/// * SampleLimit
+/// * TimeBudget
+/// * ErrorTarget
+///
+/// Which criterion ended a render.
+pub enum StopReason {
+    SampleLimit,
+    TimeBudget,
+    ErrorTarget,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::SampleLimit => write!(f, "reached the sample limit"),
+            StopReason::TimeBudget => write!(f, "ran out of time"),
+            StopReason::ErrorTarget => write!(f, "reached the error target"),
+        }
+    }
+}
+
+/// Renders the whole image, or continues `resume_from`, a film holding the
+/// given number of samples per pixel. In progressive mode `preview` gets the
+/// film between passes, at most once per preview interval. `checkpoint`
+/// gets it after every pass, with the number of samples per pixel it holds.
+///
+/// A time budget or error target also renders in passes, so the render can
+/// stop in between.
+pub fn render<const N: usize>(
+    sampler: &Sampler<N>,
+    settings: &RenderSettings,
+    resume_from: Option<(Film, u32)>,
+    mut preview: impl FnMut(&Film),
+    mut checkpoint: impl FnMut(&Film, u32),
+) -> (Film, StopReason) {
+    let start = Instant::now();
+    let (mut film, mut sample_limit) = match resume_from {
+        Some(resumed) => resumed,
+        None => (
+            Film::new(settings.img_size, settings.filter, &settings.film_aovs()),
+            0,
+        ),
+    };
+    let max_samples = settings.max_samples();
+
+    let samples_per_pass = match settings.progressive {
+        Some(progressive) => progressive.samples_per_pass,
+        None if settings.time_budget.is_some() || settings.error_target.is_some() => {
+            DEFAULT_SAMPLES_PER_PASS
+        }
+        None => max_samples,
+    };
+
+    let mut last_preview = start;
+    let mut seconds_per_sample = None;
+    let stop_reason = loop {
+        if sample_limit >= max_samples {
+            break StopReason::SampleLimit;
+        }
+
+        let mut pass_samples = samples_per_pass;
+        if let (Some(budget), Some(seconds_per_sample)) = (settings.time_budget, seconds_per_sample)
+        {
+            let remaining = budget - start.elapsed().as_secs_f32();
+            let affordable = (remaining / seconds_per_sample) as u32;
+            if affordable == 0 {
+                break StopReason::TimeBudget;
+            }
+            pass_samples = pass_samples.min(affordable);
+        }
+
+        let pass_start = Instant::now();
+        let pass_end = (sample_limit + pass_samples).min(max_samples);
+        render_pass(sampler, settings, &mut film, pass_end);
+        seconds_per_sample =
+            Some(pass_start.elapsed().as_secs_f32() / (pass_end - sample_limit) as f32);
+        sample_limit = pass_end;
+        checkpoint(&film, sample_limit);
+
+        let error = film.mean_relative_error();
+        println!(
+            "rendered {} of {} samples per pixel, mean relative error {:.4}",
+            sample_limit, max_samples, error
+        );
+        if let Some(target) = settings.error_target {
+            if error < target {
+                break StopReason::ErrorTarget;
+            }
+        }
+
+        if let Some(progressive) = settings.progressive {
+            if sample_limit < max_samples
+                && last_preview.elapsed().as_secs_f32() >= progressive.preview_interval
+            {
+                preview(&film);
+                last_preview = Instant::now();
+            }
+        }
+    };
+
+    (film, stop_reason)
+}
+
+/// Brings every pixel up to `sample_limit` samples, tile by tile on the rayon
+/// thread pool.
+fn render_pass<const N: usize>(
+    sampler: &Sampler<N>,
+    settings: &RenderSettings,
+    film: &mut Film,
+    sample_limit: u32,
+) {
+    let tiles = tiles::tiles(settings.img_size, settings.tile_size, settings.tile_order);
+    let queue = Mutex::new(TileQueue {
+        film,
+        pending: tiles.iter().map(|_| None).collect(),
+        next: 0,
+    });
+
+    // bridging hands the tiles out in order, rather than splitting the list
+    tiles
+        .iter()
+        .enumerate()
+        .par_bridge()
+        .for_each(|(i, (min, max))| {
+            let tile_start = Instant::now();
+            let mut tile = queue.lock().unwrap().film.tile(*min, *max);
+            for y in min.1..max.1 {
+                for x in min.0..max.0 {
+                    sampler.sample((x, y), &mut tile, sample_limit);
+                }
+            }
+            queue.lock().unwrap().finish(i, tile);
+            stats::flush(tile_start.elapsed().as_secs_f64());
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        render::{aov::PathSample, filter::Filter, tiles::TileOrder},
+        utils::vector::Color,
+    };
+
+    /// A tile of `film` with a few samples that reach into its neighbours.
+    fn rendered_tile(film: &Film, min: (u32, u32), max: (u32, u32)) -> FilmTile {
+        let mut tile = film.tile(min, max);
+        for y in min.1..max.1 {
+            for x in min.0..max.0 {
+                let sample = PathSample {
+                    first_hit: None,
+                    bounces: 0,
+                    color: Color::new(0.1 * x as f32, 0.7, 0.3 + 0.01 * y as f32),
+                };
+                tile.add_sample((x, y), (x as f32 + 0.3, y as f32 + 0.6), &sample);
+            }
+        }
+        tile
+    }
+
+    #[test]
+    fn tiles_merge_in_render_order() {
+        let (size, filter) = (
+            (40, 24),
+            Filter::Gaussian {
+                radius: 1.5,
+                sigma: 0.5,
+            },
+        );
+        let tiles = tiles::tiles(size, 8, TileOrder::Spiral);
+
+        let mut expected = Film::new(size, filter, &[]);
+        for &(min, max) in tiles.iter() {
+            let tile = rendered_tile(&expected, min, max);
+            expected.merge_tile(&tile);
+        }
+
+        // finish the tiles back to front; none can merge until the first does
+        let mut film = Film::new(size, filter, &[]);
+        let rendered: Vec<FilmTile> = tiles
+            .iter()
+            .map(|&(min, max)| rendered_tile(&film, min, max))
+            .collect();
+        let mut queue = TileQueue {
+            film: &mut film,
+            pending: (0..tiles.len()).map(|_| None).collect(),
+            next: 0,
+        };
+        for (index, tile) in rendered.into_iter().enumerate().rev() {
+            queue.finish(index, tile);
+            let expected_next = if index == 0 { tiles.len() } else { 0 };
+            assert_eq!(queue.next, expected_next);
+        }
+
+        for y in 0..size.1 {
+            for x in 0..size.0 {
+                let (a, b) = (film.pixel((x, y)), expected.pixel((x, y)));
+                assert_eq!((a.x, a.y, a.z), (b.x, b.y, b.z));
+            }
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use crate::utils::vector::*;
+
+const PI: f32 = std::f32::consts::PI;
+
+/// Bases for the Halton sequence, one per dimension. Dimensions past the end
+/// of the table fall back to independent random numbers.
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+/// * Independent
+/// * Stratified
+/// * Halton
+/// * Sobol
+///
+/// How the random numbers behind each sample are distributed within a pixel.
+#[allow(dead_code)]
+#[derive(Copy, Clone)]
+pub enum SamplePattern {
+    /// Uncorrelated uniform random numbers.
+    Independent,
+    /// Jittered strata, shuffled independently for every dimension.
+    Stratified,
+    /// Halton sequence with Owen-scrambled digits.
+    Halton,
+    /// Owen-scrambled, shuffled Sobol (0,2)-sequence, padded dimension pair by
+    /// dimension pair as described by Burley (2020).
+    Sobol,
+}
+
+impl SamplePattern {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SamplePattern::Independent => "independent",
+            SamplePattern::Stratified => "stratified",
+            SamplePattern::Halton => "halton",
+            SamplePattern::Sobol => "sobol",
+        }
+    }
+}
+
+/// Hands out the random numbers for one pixel. Every random decision made
+/// while tracing a sample consumes the next dimension, so a sample's choices
+/// are spread out well against the pixel's other samples.
+///
+/// All values are hashed from the render seed, the pixel, the sample index
+/// and the dimension, so a render is the same no matter which thread traces
+/// which pixel, or in what order.
+pub struct SampleGenerator {
+    pattern: SamplePattern,
+    seed: u32,
+    samples_per_pixel: u32,
+    index: u32,
+    dimension: u32,
+}
+
+impl SampleGenerator {
+    pub fn new(
+        pattern: SamplePattern,
+        pixel: (u32, u32),
+        samples_per_pixel: u32,
+        seed: u32,
+    ) -> SampleGenerator {
+        let seed = hash_combine(hash_combine(seed, pixel.0), pixel.1);
+        SampleGenerator {
+            pattern,
+            seed,
+            samples_per_pixel,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    /// Moves on to the `index`th sample of the pixel, starting again from the
+    /// first dimension.
+    pub fn start_sample(&mut self, index: u32) {
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    pub fn get_1d(&mut self) -> f32 {
+        let dimension_seed = hash_combine(self.seed, self.dimension);
+        let x = match self.pattern {
+            SamplePattern::Independent => self.uniform(dimension_seed, 0),
+            SamplePattern::Stratified => {
+                let n = self.samples_per_pixel;
+                let stratum = permute(self.index % n, n, dimension_seed);
+                (stratum as f32 + self.uniform(dimension_seed, 0)) / n as f32
+            }
+            SamplePattern::Halton => match PRIMES.get(self.dimension as usize) {
+                Some(base) => halton(*base, self.index, dimension_seed),
+                None => self.uniform(dimension_seed, 0),
+            },
+            SamplePattern::Sobol => {
+                let index = nested_uniform_scramble(self.index, dimension_seed);
+                to_unit_float(nested_uniform_scramble(
+                    sobol(index, 0),
+                    hash_combine(dimension_seed, 0),
+                ))
+            }
+        };
+        self.dimension += 1;
+        x
+    }
+
+    pub fn get_2d(&mut self) -> (f32, f32) {
+        let dimension_seed = hash_combine(self.seed, self.dimension);
+        let xy = match self.pattern {
+            SamplePattern::Independent => (
+                self.uniform(dimension_seed, 0),
+                self.uniform(dimension_seed, 1),
+            ),
+            SamplePattern::Stratified => {
+                let n = (self.samples_per_pixel as f32).sqrt().ceil() as u32;
+                let stratum = permute(self.index % (n * n), n * n, dimension_seed);
+                let (jx, jy) = (
+                    self.uniform(dimension_seed, 0),
+                    self.uniform(dimension_seed, 1),
+                );
+                (
+                    ((stratum % n) as f32 + jx) / n as f32,
+                    ((stratum / n) as f32 + jy) / n as f32,
+                )
+            }
+            SamplePattern::Halton => {
+                let next_seed = hash_combine(self.seed, self.dimension + 1);
+                match (
+                    PRIMES.get(self.dimension as usize),
+                    PRIMES.get(self.dimension as usize + 1),
+                ) {
+                    (Some(b0), Some(b1)) => (
+                        halton(*b0, self.index, dimension_seed),
+                        halton(*b1, self.index, next_seed),
+                    ),
+                    _ => (self.uniform(dimension_seed, 0), self.uniform(next_seed, 0)),
+                }
+            }
+            SamplePattern::Sobol => {
+                let index = nested_uniform_scramble(self.index, dimension_seed);
+                (
+                    to_unit_float(nested_uniform_scramble(
+                        sobol(index, 0),
+                        hash_combine(dimension_seed, 0),
+                    )),
+                    to_unit_float(nested_uniform_scramble(
+                        sobol(index, 1),
+                        hash_combine(dimension_seed, 1),
+                    )),
+                )
+            }
+        };
+        self.dimension += 2;
+        xy
+    }
+
+    /// Uncorrelated uniform number for the current sample, hashed from the
+    /// dimension's seed and `salt`.
+    fn uniform(&self, dimension_seed: u32, salt: u32) -> f32 {
+        to_unit_float(hash(hash_combine(
+            hash_combine(dimension_seed, self.index),
+            salt,
+        )))
+    }
+
+    /// Uniform point in the unit disk on the xy plane, using the concentric
+    /// mapping so strata stay compact.
+    pub fn in_unit_disk(&mut self) -> Vec3 {
+        let (x, y) = self.get_2d();
+        let (a, b) = (2.0 * x - 1.0, 2.0 * y - 1.0);
+        if a == 0.0 && b == 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let (r, theta) = if a.abs() > b.abs() {
+            (a, PI / 4.0 * (b / a))
+        } else {
+            (b, PI / 2.0 - PI / 4.0 * (a / b))
+        };
+        Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
+    }
+
+    /// Uniform direction on the unit sphere.
+    pub fn unit_vector(&mut self) -> Vec3 {
+        let (x, y) = self.get_2d();
+        let z = 1.0 - 2.0 * x;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * y;
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
+    }
+
+    /// Uniform point in the unit ball.
+    pub fn in_unit_sphere(&mut self) -> Vec3 {
+        let direction = self.unit_vector();
+        self.get_1d().cbrt() * direction
+    }
+}
+
+fn to_unit_float(x: u32) -> f32 {
+    // keep 24 bits so the result stays below 1.0
+    (x >> 8) as f32 / (1u32 << 24) as f32
+}
+
+/// Integer hash with good avalanche (lowbias32 by Chris Wellons).
+pub fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846ca68b);
+    x ^= x >> 16;
+    x
+}
+
+pub fn hash_combine(seed: u32, v: u32) -> u32 {
+    seed ^ (hash(v)
+        .wrapping_add(0x9e3779b9)
+        .wrapping_add(seed << 6)
+        .wrapping_add(seed >> 2))
+}
+
+/// Maps `i` to its place in a random permutation of `0..l` chosen by `p`,
+/// without storing the permutation (Kensler 2013).
+fn permute(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % l
+}
+
+/// Radical inverse of `index` in a prime `base`, with each digit permuted
+/// depending on the digits before it (Owen scrambling).
+fn halton(base: u32, index: u32, seed: u32) -> f32 {
+    let inv_base = 1.0 / base as f64;
+    let mut scale = inv_base;
+    let mut result = 0.0;
+    let mut remaining = index;
+    let mut prefix = seed;
+    // keep going past the last nonzero digit, since scrambling fills those in
+    while scale > 1e-8 {
+        let digit = remaining % base;
+        remaining /= base;
+        result += permute(digit, base, hash(prefix)) as f64 * scale;
+        prefix = hash_combine(prefix, digit);
+        scale *= inv_base;
+    }
+    (result as f32).min(1.0 - f32::EPSILON)
+}
+
+/// Element of the first two dimensions of the Sobol sequence.
+fn sobol(index: u32, dimension: u32) -> u32 {
+    let mut x = 0;
+    let mut direction: u32 = 1 << 31;
+    for bit in 0..32 {
+        if (index >> bit) & 1 == 1 {
+            x ^= direction;
+        }
+        direction = match dimension {
+            // van der Corput
+            0 => direction >> 1,
+            // primitive polynomial x + 1
+            _ => direction ^ (direction >> 1),
+        };
+    }
+    x
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATTERNS: [SamplePattern; 4] = [
+        SamplePattern::Independent,
+        SamplePattern::Stratified,
+        SamplePattern::Halton,
+        SamplePattern::Sobol,
+    ];
+
+    /// The first few dimensions of sample `index` of pixel (3, 5).
+    fn sample(pattern: SamplePattern, seed: u32, index: u32) -> Vec<f32> {
+        let mut sampler = SampleGenerator::new(pattern, (3, 5), 16, seed);
+        sampler.start_sample(index);
+        let (u, v) = sampler.get_2d();
+        vec![sampler.get_1d(), u, v, sampler.get_1d(), sampler.get_1d()]
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_samples_in_any_order() {
+        for pattern in PATTERNS {
+            let forward: Vec<Vec<f32>> = (0..16).map(|i| sample(pattern, 42, i)).collect();
+            let mut backward: Vec<Vec<f32>> =
+                (0..16).rev().map(|i| sample(pattern, 42, i)).collect();
+            backward.reverse();
+            assert_eq!(forward, backward);
+            assert!(forward.iter().flatten().all(|x| (0.0..1.0).contains(x)));
+        }
+    }
+
+    #[test]
+    fn other_seeds_give_other_samples() {
+        for pattern in PATTERNS {
+            for i in 0..16 {
+                assert_ne!(sample(pattern, 42, i), sample(pattern, 43, i));
+            }
+        }
+    }
+
+    #[test]
+    fn stratified_samples_fill_every_stratum() {
+        for pattern in [SamplePattern::Stratified, SamplePattern::Sobol] {
+            let mut strata = [0; 16];
+            for i in 0..16 {
+                let x = sample(pattern, 7, i)[0];
+                strata[(x * 16.0) as usize] += 1;
+            }
+            assert_eq!(strata, [1; 16]);
+        }
+    }
+}
@@ -1,25 +1,94 @@
-const CLIP_RANGE: (f32, f32) = (0.001, f32::INFINITY);
+pub const CLIP_RANGE: (f32, f32) = (0.001, f32::INFINITY);
+/// Adaptive sampling only checks a pixel's error this often, in samples.
+const ADAPTIVE_BATCH: u32 = 8;
+/// Even bright paths are terminated this rarely, so no path runs forever.
+const MAX_SURVIVAL: f32 = 0.95;
 
 extern crate image;
 
-use crate::utils::{ray::Ray, vector::*};
+use crate::utils::{color::luminance, ray::Ray, vector::*};
 
-use super::{materials::material::Scatterer, scene::Scene, shapes::hit::Hittable};
+use super::{
+    aov::{FirstHit, PathSample},
+    camera::Camera,
+    film::FilmTile,
+    materials::material::Scatterer,
+    sample_generator::SampleGenerator,
+    scene::Scene,
+    shapes::hit::Hittable,
+    stats::{self, Termination},
+};
 
-use rand::prelude::*;
+/// Follows a path from the camera until it escapes to the environment, and
+/// returns the light it carries back. What the camera ray hit is only kept
+/// when the render has AOVs.
+fn sample_ray<const N: usize>(mut r: Ray, s: &Scene<N>, gen: &mut SampleGenerator) -> PathSample {
+    let settings = &s.render_settings;
+    let mut first_hit = None;
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    let black = Color::new(0.0, 0.0, 0.0);
+    // bounces taken so far, per lobe
+    let mut bounces = [0u32; 4];
+    let mut depth = 0;
+    let mut rays = 0;
 
-fn sample_ray<const N: usize>(r: &Ray, s: &Scene::<N>, depth: u32) -> Color {
-    if depth <= 0 {
-        return Color::new(0.0, 0.0, 0.0);
-    }
-    match s.hit(r, CLIP_RANGE) {
-        Some(hit_data) => {
-            let (scattered, attenuation) = hit_data.material.scatter(r, &hit_data);
-            return attenuation * sample_ray(&scattered, s, depth - 1);
+    let (color, termination) = loop {
+        if depth == settings.max_depth {
+            break (black, Termination::MaxDepth);
+        }
+        rays += 1;
+        let hit_data = match s.hit(&r, CLIP_RANGE) {
+            Some(hit_data) => hit_data,
+            None => {
+                break (
+                    throughput * s.environment.sky_color(&r),
+                    Termination::Escaped,
+                )
+            }
+        };
+
+        let (scattered, attenuation, lobe) = hit_data.material.scatter(&r, &hit_data, gen);
+        if depth == 0 && !settings.aovs.is_empty() {
+            first_hit = Some(FirstHit {
+                dist: hit_data.dist,
+                normal: hit_data.normal,
+                albedo: hit_data.material.albedo(),
+                p: hit_data.p,
+                material_id: hit_data.material.id(),
+                object_id: hit_data.object as u32,
+                lobe,
+            });
         }
-        None => {
-            return s.environment.sky_color(r);
+        bounces[lobe as usize] += 1;
+        if bounces[lobe as usize] > settings.bounce_limits.get(lobe) {
+            break (black, Termination::BounceLimit);
         }
+
+        throughput = throughput * attenuation;
+        let brightest = throughput.x.max(throughput.y).max(throughput.z);
+        if brightest <= 0.0 {
+            break (black, Termination::Absorbed);
+        }
+        if depth + 1 >= settings.roulette_depth {
+            let survival = brightest.min(MAX_SURVIVAL);
+            if gen.get_1d() >= survival {
+                break (black, Termination::RussianRoulette);
+            }
+            throughput /= survival;
+        }
+        r = scattered;
+        depth += 1;
+    };
+
+    stats::record(|stats| {
+        stats.camera_rays += rays.min(1);
+        stats.bounce_rays += rays.saturating_sub(1);
+        stats.record_path(depth as usize, termination);
+    });
+    PathSample {
+        first_hit,
+        bounces: depth,
+        color,
     }
 }
 
@@ -32,21 +101,67 @@ impl<const N: usize> Sampler<'_, N> {
         Sampler { scene }
     }
 
-    pub fn sample(&self, pos: (u32, u32), samples: u32) -> Color {
-        let mut color = Color::new(0.0, 0.0, 0.0);
+    /// Splats samples of a pixel into `tile` until it has `sample_limit` of
+    /// them in total, or adaptive sampling decides it is done.
+    pub fn sample(&self, pos: (u32, u32), tile: &mut FilmTile, sample_limit: u32) {
+        let settings = &self.scene.render_settings;
+        let (min_samples, threshold) = match settings.adaptive {
+            Some(adaptive) => (adaptive.min_samples, adaptive.threshold),
+            None => (settings.samples_per_pixel, 0.0),
+        };
+        let max_samples = settings.max_samples();
 
-        for _ in 0..samples {
-            let rx: f32 = random();
-            let ry: f32 = random();
+        let mut gen =
+            SampleGenerator::new(settings.sample_pattern, pos, max_samples, settings.seed);
+        let mut stats = *tile.stats_mut(pos);
 
-            let u = (pos.0 as f32 + rx) / (self.scene.render_settings.img_size.0 - 1) as f32;
-            let v = (pos.1 as f32 + ry) / (self.scene.render_settings.img_size.1 - 1) as f32;
-            let r = self.scene.camera.get_ray(u, v);
+        while !stats.converged && stats.samples < sample_limit.min(max_samples) {
+            gen.start_sample(stats.samples);
+            let (rx, ry) = gen.get_2d();
+            let film_pos = (pos.0 as f32 + rx, pos.1 as f32 + ry);
 
-            color += sample_ray(&r, &self.scene, self.scene.render_settings.max_depth);
+            let u = film_pos.0 / (self.scene.render_settings.img_size.0 - 1) as f32;
+            let v = film_pos.1 / (self.scene.render_settings.img_size.1 - 1) as f32;
+            let sample = match self.scene.camera.get_ray(u, v, &mut gen) {
+                Some((r, weight)) => {
+                    let sample = sample_ray(r, &self.scene, &mut gen);
+                    PathSample {
+                        color: weight * sample.color,
+                        ..sample
+                    }
+                }
+                None => PathSample {
+                    first_hit: None,
+                    bounces: 0,
+                    color: Color::new(0.0, 0.0, 0.0),
+                },
+            };
+            if stats.samples == 0 {
+                tile.set_ids(pos, &sample);
+            }
+            tile.add_sample(pos, film_pos, &sample);
+            stats.samples += 1;
+
+            let l = luminance(self.expose(sample.color), settings.working_space);
+            let delta = l - stats.mean;
+            stats.mean += delta / stats.samples as f32;
+            stats.m2 += delta * (l - stats.mean);
+
+            let n = stats.samples;
+            if n >= min_samples && n.is_multiple_of(ADAPTIVE_BATCH) {
+                stats.converged = stats.relative_error() < threshold;
+            }
         }
-        let avg = color / (samples as f32);
 
-        return avg;
+        *tile.stats_mut(pos) = stats;
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.scene.camera
+    }
+
+    /// Turns reconstructed radiance into the color recorded by the camera.
+    pub fn expose(&self, radiance: Color) -> Color {
+        self.scene.camera.expose(radiance)
     }
 }
@@ -1,7 +1,8 @@
 use super::{
     camera::Camera,
+    sampler::CLIP_RANGE,
     materials::environment::Environment,
-    shapes::{hit::*, Shape},
+    shapes::{bvh::Bvh, hit::*, Shape},
 };
 
 use crate::{scenes::RenderSettings, utils::ray::Ray};
@@ -11,21 +12,44 @@ pub struct Scene<'a, const NUM_COMPONENTS: usize> {
     pub environment: Environment<'a>,
     pub camera: Camera,
     pub render_settings: RenderSettings,
+    bvh: Bvh,
 }
 
 unsafe impl<const NUM_COMPONENTS: usize> Send for Scene<'_, NUM_COMPONENTS> {}
 
-impl<const NUM_COMPONENTS: usize> Hittable for Scene<'_, NUM_COMPONENTS> {
-    fn hit(&self, r: &Ray, dist_range: (f32, f32)) -> Option<HitData> {
-        let mut closest_so_far = dist_range.1;
-        let mut hit_data_option: Option<HitData> = None;
+impl<'a, const NUM_COMPONENTS: usize> Scene<'a, NUM_COMPONENTS> {
+    /// Builds the BVH over `components`, bounding moving shapes across the
+    /// camera's shutter interval, and resolves the camera's autofocus.
+    pub fn new(
+        components: [Shape; NUM_COMPONENTS],
+        environment: Environment<'a>,
+        camera: Camera,
+        render_settings: RenderSettings,
+    ) -> Scene<'a, NUM_COMPONENTS> {
+        let bvh = Bvh::new(&components, camera.shutter);
+        let mut scene = Scene {
+            components,
+            environment,
+            camera,
+            render_settings,
+            bvh,
+        };
 
-        for obj in self.components.iter() {
-            if let Some(hit_data) = obj.hit(r, (dist_range.0, closest_so_far)) {
-                closest_so_far = hit_data.dist;
-                hit_data_option = Some(hit_data);
-            }
+        let focus_dist = scene
+            .camera
+            .autofocus_ray()
+            .and_then(|r| scene.hit(&r, CLIP_RANGE))
+            .map(|hit_data| hit_data.dist);
+        if let Some(focus_dist) = focus_dist {
+            scene.camera.set_focus_dist(focus_dist);
         }
-        return hit_data_option;
+
+        scene
+    }
+}
+
+impl<const NUM_COMPONENTS: usize> Hittable for Scene<'_, NUM_COMPONENTS> {
+    fn hit(&self, r: &Ray, dist_range: (f32, f32)) -> Option<HitData> {
+        self.bvh.hit(&self.components, r, dist_range)
     }
 }
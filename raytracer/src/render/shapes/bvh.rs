@@ -0,0 +1,243 @@
+use super::{hit::*, Shape};
+use crate::render::stats;
+use crate::utils::{
+    aabb::{surrounding_box, Aabb},
+    ray::Ray,
+};
+
+/// * Leaf(bbox: Aabb, shape: usize)
+/// * Interior(bbox: Aabb, left: usize, right: usize)
+enum BvhNode {
+    Leaf {
+        bbox: Aabb,
+        shape: usize,
+    },
+    Interior {
+        bbox: Aabb,
+        left: usize,
+        right: usize,
+    },
+}
+
+impl BvhNode {
+    fn bbox(&self) -> Aabb {
+        match self {
+            BvhNode::Leaf { bbox, .. } => *bbox,
+            BvhNode::Interior { bbox, .. } => *bbox,
+        }
+    }
+}
+
+/// Bounding volume hierarchy over a scene's components. Nodes are stored
+/// flat and refer to shapes by their index in the component array.
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+}
+
+impl Bvh {
+    pub fn new(shapes: &[Shape], shutter: (f32, f32)) -> Bvh {
+        let mut nodes = Vec::with_capacity(2 * shapes.len());
+        let mut items: Vec<(usize, Aabb)> = shapes
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (i, s.bounding_box(shutter)))
+            .collect();
+
+        if !items.is_empty() {
+            build(&mut nodes, &mut items);
+        }
+
+        Bvh { nodes }
+    }
+
+    pub fn hit<'a>(
+        &self,
+        shapes: &'a [Shape],
+        r: &Ray,
+        dist_range: (f32, f32),
+    ) -> Option<HitData<'a>> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let mut closest_so_far = dist_range.1;
+        let mut hit_data_option: Option<HitData> = None;
+        // median splits keep the tree balanced, so this never overflows
+        let mut stack = [0usize; 64];
+        let mut stack_len = 1;
+        let (mut node_tests, mut shape_tests) = (0, 0);
+
+        while stack_len > 0 {
+            stack_len -= 1;
+            let node = &self.nodes[stack[stack_len]];
+            node_tests += 1;
+            if !node.bbox().hit(r, (dist_range.0, closest_so_far)) {
+                continue;
+            }
+            match node {
+                BvhNode::Leaf { shape, .. } => {
+                    shape_tests += 1;
+                    if let Some(hit_data) = shapes[*shape].hit(r, (dist_range.0, closest_so_far)) {
+                        closest_so_far = hit_data.dist;
+                        hit_data_option = Some(HitData {
+                            object: *shape,
+                            ..hit_data
+                        });
+                    }
+                }
+                BvhNode::Interior { left, right, .. } => {
+                    stack[stack_len] = *right;
+                    stack[stack_len + 1] = *left;
+                    stack_len += 2;
+                }
+            }
+        }
+
+        stats::record(|stats| {
+            stats.bvh_node_tests += node_tests;
+            stats.shape_tests += shape_tests;
+        });
+        hit_data_option
+    }
+}
+
+/// Recursively splits `items` at the median of the longest centroid axis and
+/// returns the index of the node covering them.
+fn build(nodes: &mut Vec<BvhNode>, items: &mut [(usize, Aabb)]) -> usize {
+    let bbox = items
+        .iter()
+        .skip(1)
+        .fold(items[0].1, |acc, (_, b)| surrounding_box(acc, *b));
+
+    if items.len() == 1 {
+        nodes.push(BvhNode::Leaf {
+            bbox,
+            shape: items[0].0,
+        });
+        return nodes.len() - 1;
+    }
+
+    let extent = bbox.max - bbox.min;
+    let axis = if extent.x > extent.y && extent.x > extent.z {
+        0
+    } else if extent.y > extent.z {
+        1
+    } else {
+        2
+    };
+    let key = |b: &Aabb| {
+        let c = b.centroid();
+        match axis {
+            0 => c.x,
+            1 => c.y,
+            _ => c.z,
+        }
+    };
+    items.sort_by(|a, b| key(&a.1).total_cmp(&key(&b.1)));
+
+    // reserve this node's slot before its children so the root ends up at 0
+    let idx = nodes.len();
+    nodes.push(BvhNode::Leaf { bbox, shape: 0 });
+
+    let (left_items, right_items) = items.split_at_mut(items.len() / 2);
+    let left = build(nodes, left_items);
+    let right = build(nodes, right_items);
+    nodes[idx] = BvhNode::Interior { bbox, left, right };
+
+    idx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{render::materials::material::Material, utils::vector::*};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    const SHUTTER: (f32, f32) = (0.0, 1.0);
+
+    fn random_point(rng: &mut StdRng, extent: f32) -> Point3 {
+        Point3::new(
+            rng.gen_range(-extent, extent),
+            rng.gen_range(-extent, extent),
+            rng.gen_range(-extent, extent),
+        )
+    }
+
+    /// A mix of static and moving spheres scattered around the origin.
+    fn shapes(rng: &mut StdRng) -> Vec<Shape> {
+        (0..200)
+            .map(|i| {
+                let center = random_point(rng, 10.0);
+                let radius = rng.gen_range(0.1, 1.0);
+                let material = Material::Lambertian {
+                    albedo: Color::new(0.5, 0.5, 0.5),
+                };
+                if i % 3 == 0 {
+                    Shape::MovingSphere {
+                        center: (center, center + random_point(rng, 1.0)),
+                        time: SHUTTER,
+                        radius,
+                        material,
+                    }
+                } else {
+                    Shape::Sphere {
+                        center,
+                        radius,
+                        material,
+                    }
+                }
+            })
+            .collect()
+    }
+
+    fn brute_force<'a>(
+        shapes: &'a [Shape],
+        r: &Ray,
+        dist_range: (f32, f32),
+    ) -> Option<HitData<'a>> {
+        let mut closest: Option<HitData> = None;
+        for (i, shape) in shapes.iter().enumerate() {
+            let max = closest.as_ref().map_or(dist_range.1, |hit| hit.dist);
+            if let Some(hit) = shape.hit(r, (dist_range.0, max)) {
+                closest = Some(HitData { object: i, ..hit });
+            }
+        }
+        closest
+    }
+
+    #[test]
+    fn finds_the_same_closest_hit_as_a_brute_force_search() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let shapes = shapes(&mut rng);
+        let bvh = Bvh::new(&shapes, SHUTTER);
+
+        let mut hits = 0;
+        for _ in 0..2000 {
+            // from outside the cloud of spheres, roughly towards it
+            let origin = random_point(&mut rng, 1.0).unit() * 20.0;
+            let direction = random_point(&mut rng, 5.0) - origin;
+            let r = Ray::new(origin, direction, rng.gen_range(SHUTTER.0, SHUTTER.1));
+            let range = (0.001, f32::INFINITY);
+
+            let (found, expected) = (bvh.hit(&shapes, &r, range), brute_force(&shapes, &r, range));
+            match (found, expected) {
+                (Some(found), Some(expected)) => {
+                    assert_eq!(found.object, expected.object);
+                    assert_eq!(found.dist, expected.dist);
+                    hits += 1;
+                }
+                (None, None) => {}
+                _ => panic!("the BVH and brute force disagree on whether a ray hits"),
+            }
+        }
+        // most rays should hit something for the comparison to mean much
+        assert!(hits > 1000, "only {} hits", hits);
+    }
+
+    #[test]
+    fn empty_scenes_are_never_hit() {
+        let bvh = Bvh::new(&[], SHUTTER);
+        let r = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(bvh.hit(&[], &r, (0.001, f32::INFINITY)).is_none());
+    }
+}
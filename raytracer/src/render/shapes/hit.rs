@@ -9,6 +9,8 @@ pub struct HitData<'a> {
     pub dist: f32,
     pub front_face: bool,
     pub material: &'a Material,
+    /// Index of the shape in the scene's components, filled in by the BVH.
+    pub object: usize,
 }
 
 pub trait Hittable {
@@ -1,8 +1,13 @@
+pub mod bvh;
 pub mod hit;
 
 use crate::{
     render::materials::material::*,
-    utils::{ray::Ray, vector::*},
+    utils::{
+        aabb::{surrounding_box, Aabb},
+        ray::Ray,
+        vector::*,
+    },
 };
 use hit::*;
 
@@ -12,6 +17,33 @@ pub enum Shape {
         radius: f32,
         material: Material,
     },
+    /// A sphere whose center moves linearly from `center.0` at `time.0` to
+    /// `center.1` at `time.1`. Equal times keep it at `center.0`.
+    MovingSphere {
+        center: (Point3, Point3),
+        time: (f32, f32),
+        radius: f32,
+        material: Material,
+    },
+}
+
+impl Shape {
+    /// Bounds the shape over the whole shutter interval, so moving shapes can
+    /// still be placed in the BVH.
+    pub fn bounding_box(&self, shutter: (f32, f32)) -> Aabb {
+        match self {
+            Shape::Sphere { center, radius, .. } => sphere_box(*center, *radius),
+            Shape::MovingSphere {
+                center,
+                time,
+                radius,
+                ..
+            } => surrounding_box(
+                sphere_box(moving_center(*center, *time, shutter.0), *radius),
+                sphere_box(moving_center(*center, *time, shutter.1), *radius),
+            ),
+        }
+    }
 }
 
 impl Hittable for Shape {
@@ -22,10 +54,35 @@ impl Hittable for Shape {
                 radius,
                 material,
             } => sphere_hit(*center, *radius, material, r, dist_range),
+            Shape::MovingSphere {
+                center,
+                time,
+                radius,
+                material,
+            } => sphere_hit(
+                moving_center(*center, *time, r.time),
+                *radius,
+                material,
+                r,
+                dist_range,
+            ),
         }
     }
 }
 
+fn moving_center(center: (Point3, Point3), time: (f32, f32), t: f32) -> Point3 {
+    if time.0 == time.1 {
+        return center.0;
+    }
+    let progress = (t - time.0) / (time.1 - time.0);
+    center.0 + progress * (center.1 - center.0)
+}
+
+fn sphere_box(center: Point3, radius: f32) -> Aabb {
+    let r = Vec3::new(radius, radius, radius);
+    Aabb::new(center - r, center + r)
+}
+
 fn sphere_hit<'a>(
     center: Point3,
     radius: f32,
@@ -76,7 +133,58 @@ fn sphere_hit<'a>(
             false => -outward_normal,
         },
         material: material,
+        object: 0,
     };
 
     Some(data)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contains(outer: Aabb, inner: Aabb) -> bool {
+        let below = |a: Vec3, b: Vec3| a.x <= b.x && a.y <= b.y && a.z <= b.z;
+        below(outer.min, inner.min) && below(inner.max, outer.max)
+    }
+
+    fn moving_sphere(center: (Point3, Point3), radius: f32) -> Shape {
+        Shape::MovingSphere {
+            center,
+            time: (0.0, 1.0),
+            radius,
+            material: Material::Lambertian {
+                albedo: Color::new(0.5, 0.5, 0.5),
+            },
+        }
+    }
+
+    #[test]
+    fn moving_sphere_bounds_cover_the_whole_shutter() {
+        let center = (Point3::new(0.0, 0.0, 0.0), Point3::new(2.0, -1.0, 0.5));
+        let sphere = moving_sphere(center, 0.3);
+        // the shutter can be shorter than the motion or run past it
+        for shutter in [(0.0, 1.0), (0.25, 0.5), (-0.5, 1.5)] {
+            let bbox = sphere.bounding_box(shutter);
+            for i in 0..=10 {
+                let t = shutter.0 + (shutter.1 - shutter.0) * i as f32 / 10.0;
+                let at_t = sphere_box(moving_center(center, (0.0, 1.0), t), 0.3);
+                assert!(contains(bbox, at_t), "shutter {:?} at {}", shutter, t);
+            }
+        }
+    }
+
+    #[test]
+    fn moving_spheres_are_hit_where_they_are_at_the_ray_time() {
+        let sphere = moving_sphere(
+            (Point3::new(0.0, 0.0, -5.0), Point3::new(3.0, 0.0, -5.0)),
+            0.5,
+        );
+        let forward = Vec3::new(0.0, 0.0, -1.0);
+        let range = (0.001, f32::INFINITY);
+        let ray = |x: f32, time: f32| Ray::new(Point3::new(x, 0.0, 0.0), forward, time);
+        assert!(sphere.hit(&ray(0.0, 0.0), range).is_some());
+        assert!(sphere.hit(&ray(0.0, 1.0), range).is_none());
+        assert!(sphere.hit(&ray(3.0, 1.0), range).is_some());
+    }
+}
@@ -0,0 +1,195 @@
+use std::{cell::RefCell, sync::Mutex};
+
+/// * Escaped
+/// * Absorbed
+/// * RussianRoulette
+/// * BounceLimit
+/// * MaxDepth
+///
+/// Why a path stopped.
+#[derive(Copy, Clone)]
+pub enum Termination {
+    /// Left the scene and picked up the environment.
+    Escaped,
+    /// Its throughput dropped to black.
+    Absorbed,
+    RussianRoulette,
+    /// Took more bounces of one kind than its limit allows.
+    BounceLimit,
+    MaxDepth,
+}
+
+const TERMINATIONS: [(Termination, &str); 5] = [
+    (Termination::Escaped, "escaped"),
+    (Termination::Absorbed, "absorbed"),
+    (Termination::RussianRoulette, "russian_roulette"),
+    (Termination::BounceLimit, "bounce_limit"),
+    (Termination::MaxDepth, "max_depth"),
+];
+
+/// Counters collected while rendering. Every thread counts into its own
+/// copy, which gets added to the totals after each tile, so counting never
+/// waits on a lock.
+#[derive(Clone, Default)]
+pub struct RenderStats {
+    pub camera_rays: u64,
+    pub bounce_rays: u64,
+    /// No integrator traces shadow rays yet, so this stays zero until one
+    /// samples lights directly.
+    pub shadow_rays: u64,
+    pub shape_tests: u64,
+    pub bvh_node_tests: u64,
+    /// Number of paths by how many bounces they took.
+    pub path_lengths: Vec<u64>,
+    /// Number of paths by `Termination`.
+    pub terminations: [u64; 5],
+    /// Seconds each rayon thread spent rendering tiles, by thread index.
+    pub thread_seconds: Vec<f64>,
+}
+
+thread_local! {
+    static LOCAL: RefCell<RenderStats> = RefCell::new(RenderStats::default());
+}
+
+static TOTALS: Mutex<Option<RenderStats>> = Mutex::new(None);
+
+/// Updates the calling thread's counters.
+pub fn record(f: impl FnOnce(&mut RenderStats)) {
+    LOCAL.with(|stats| f(&mut stats.borrow_mut()));
+}
+
+/// Adds the calling thread's counters to the totals and resets them.
+/// `seconds` is the time the thread just spent rendering.
+pub fn flush(seconds: f64) {
+    let local = LOCAL.with(|stats| stats.replace(RenderStats::default()));
+    let mut totals = TOTALS.lock().unwrap();
+    let totals = totals.get_or_insert_with(RenderStats::default);
+    totals.add(&local);
+
+    let thread = rayon::current_thread_index().unwrap_or(0);
+    if totals.thread_seconds.len() <= thread {
+        totals.thread_seconds.resize(thread + 1, 0.0);
+    }
+    totals.thread_seconds[thread] += seconds;
+}
+
+/// Counters of all threads, as of their last flush.
+pub fn totals() -> RenderStats {
+    TOTALS.lock().unwrap().clone().unwrap_or_default()
+}
+
+impl RenderStats {
+    fn add(&mut self, other: &RenderStats) {
+        self.camera_rays += other.camera_rays;
+        self.bounce_rays += other.bounce_rays;
+        self.shadow_rays += other.shadow_rays;
+        self.shape_tests += other.shape_tests;
+        self.bvh_node_tests += other.bvh_node_tests;
+        if self.path_lengths.len() < other.path_lengths.len() {
+            self.path_lengths.resize(other.path_lengths.len(), 0);
+        }
+        for (total, n) in self.path_lengths.iter_mut().zip(other.path_lengths.iter()) {
+            *total += n;
+        }
+        for (total, n) in self.terminations.iter_mut().zip(other.terminations.iter()) {
+            *total += n;
+        }
+    }
+
+    /// Counts a finished path.
+    pub fn record_path(&mut self, bounces: usize, termination: Termination) {
+        if self.path_lengths.len() <= bounces {
+            self.path_lengths.resize(bounces + 1, 0);
+        }
+        self.path_lengths[bounces] += 1;
+        self.terminations[termination as usize] += 1;
+    }
+
+    fn rays(&self) -> u64 {
+        self.camera_rays + self.bounce_rays + self.shadow_rays
+    }
+
+    /// Human readable summary for a render that took `seconds`.
+    pub fn report(&self, seconds: f64) -> String {
+        let rays = self.rays().max(1) as f64;
+        let paths = self.camera_rays.max(1) as f64;
+        let mut out = format!(
+            "rays: {} camera, {} bounce, {} shadow ({:.2} million per second)\n",
+            self.camera_rays,
+            self.bounce_rays,
+            self.shadow_rays,
+            rays / seconds / 1e6
+        );
+        out.push_str(&format!(
+            "tests per ray: {:.2} shapes, {:.2} bvh nodes\n",
+            self.shape_tests as f64 / rays,
+            self.bvh_node_tests as f64 / rays
+        ));
+        out.push_str("path lengths:\n");
+        for (bounces, n) in self.path_lengths.iter().enumerate() {
+            out.push_str(&format!(
+                "  {:3} {:6.2}%\n",
+                bounces,
+                100.0 * *n as f64 / paths
+            ));
+        }
+        out.push_str("terminations:\n");
+        for (termination, name) in TERMINATIONS.iter() {
+            let n = self.terminations[*termination as usize];
+            out.push_str(&format!(
+                "  {:16} {:6.2}%\n",
+                name,
+                100.0 * n as f64 / paths
+            ));
+        }
+        out.push_str("thread seconds:");
+        for seconds in self.thread_seconds.iter() {
+            out.push_str(&format!(" {:.2}", seconds));
+        }
+        out
+    }
+
+    /// The counters as a JSON object, for comparing runs.
+    pub fn to_json(&self, seconds: f64) -> String {
+        let join = |values: Vec<String>| values.join(", ");
+        let terminations = TERMINATIONS
+            .iter()
+            .map(|(termination, name)| {
+                format!("\"{}\": {}", name, self.terminations[*termination as usize])
+            })
+            .collect();
+        let fields = [
+            ("seconds", seconds.to_string()),
+            (
+                "rays_per_second",
+                (self.rays() as f64 / seconds).to_string(),
+            ),
+            ("camera_rays", self.camera_rays.to_string()),
+            ("bounce_rays", self.bounce_rays.to_string()),
+            ("shadow_rays", self.shadow_rays.to_string()),
+            ("shape_tests", self.shape_tests.to_string()),
+            ("bvh_node_tests", self.bvh_node_tests.to_string()),
+            (
+                "path_lengths",
+                format!(
+                    "[{}]",
+                    join(self.path_lengths.iter().map(u64::to_string).collect())
+                ),
+            ),
+            ("terminations", format!("{{{}}}", join(terminations))),
+            (
+                "thread_seconds",
+                format!(
+                    "[{}]",
+                    join(self.thread_seconds.iter().map(f64::to_string).collect())
+                ),
+            ),
+        ];
+
+        let lines: Vec<String> = fields
+            .iter()
+            .map(|(key, value)| format!("  \"{}\": {}", key, value))
+            .collect();
+        format!("{{\n{}\n}}\n", lines.join(",\n"))
+    }
+}
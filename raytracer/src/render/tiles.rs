@@ -0,0 +1,148 @@
+/// * Scanline
+/// * Spiral
+/// * Hilbert
+///
+/// Order in which tiles are handed to the render threads.
+#[allow(dead_code)]
+#[derive(Copy, Clone)]
+pub enum TileOrder {
+    /// Row by row from the bottom of the image.
+    Scanline,
+    /// Outwards from the center, so the subject usually shows up first.
+    Spiral,
+    /// Along a Hilbert curve, which keeps consecutive tiles close together.
+    Hilbert,
+}
+
+/// Splits an image into tiles of at most `tile_size` pixels squared and
+/// returns the `(min, max)` pixel bounds of each, in render order. `max` is
+/// exclusive.
+pub fn tiles(
+    img_size: (u32, u32),
+    tile_size: u32,
+    order: TileOrder,
+) -> Vec<((u32, u32), (u32, u32))> {
+    let grid = (
+        img_size.0.div_ceil(tile_size),
+        img_size.1.div_ceil(tile_size),
+    );
+
+    let cells = match order {
+        TileOrder::Scanline => (0..grid.1)
+            .flat_map(|y| (0..grid.0).map(move |x| (x, y)))
+            .collect(),
+        TileOrder::Spiral => spiral(grid),
+        TileOrder::Hilbert => {
+            let n = grid.0.max(grid.1).next_power_of_two();
+            let mut cells: Vec<(u32, u32)> = (0..grid.1)
+                .flat_map(|y| (0..grid.0).map(move |x| (x, y)))
+                .collect();
+            cells.sort_by_key(|&cell| hilbert_index(n, cell));
+            cells
+        }
+    };
+
+    cells
+        .into_iter()
+        .map(|(x, y)| {
+            let min = (x * tile_size, y * tile_size);
+            let max = (
+                (min.0 + tile_size).min(img_size.0),
+                (min.1 + tile_size).min(img_size.1),
+            );
+            (min, max)
+        })
+        .collect()
+}
+
+/// Cells of a grid visited in a square spiral around its center.
+fn spiral(grid: (u32, u32)) -> Vec<(u32, u32)> {
+    let total = (grid.0 * grid.1) as usize;
+    let mut cells = Vec::with_capacity(total);
+    let (mut x, mut y) = ((grid.0 as i32 - 1) / 2, (grid.1 as i32 - 1) / 2);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut leg = 0;
+
+    let in_grid = |x: i32, y: i32| x >= 0 && y >= 0 && x < grid.0 as i32 && y < grid.1 as i32;
+    if in_grid(x, y) {
+        cells.push((x as u32, y as u32));
+    }
+    while cells.len() < total {
+        // legs grow by one every second turn: 1, 1, 2, 2, 3, 3, ...
+        let (dx, dy) = directions[leg % 4];
+        for _ in 0..(leg / 2 + 1) {
+            x += dx;
+            y += dy;
+            if in_grid(x, y) {
+                cells.push((x as u32, y as u32));
+            }
+        }
+        leg += 1;
+    }
+    cells
+}
+
+/// Distance along the Hilbert curve filling an `n` by `n` grid, where `n` is
+/// a power of two.
+fn hilbert_index(n: u32, cell: (u32, u32)) -> u32 {
+    let (mut x, mut y) = cell;
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as u32;
+        let ry = (y & s > 0) as u32;
+        d += s * s * ((3 * rx) ^ ry);
+        // rotate the quadrant so the curve stays continuous
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORDERS: [TileOrder; 3] = [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert];
+
+    #[test]
+    fn tiles_cover_every_pixel_once() {
+        for &(img_size, tile_size) in &[((100, 37), 16), ((1, 1), 32), ((64, 64), 32), ((5, 90), 7)]
+        {
+            for order in ORDERS {
+                let mut covered = vec![0; (img_size.0 * img_size.1) as usize];
+                for (min, max) in tiles(img_size, tile_size, order) {
+                    assert!(max.0 - min.0 <= tile_size && max.1 - min.1 <= tile_size);
+                    for y in min.1..max.1 {
+                        for x in min.0..max.0 {
+                            covered[(x + y * img_size.0) as usize] += 1;
+                        }
+                    }
+                }
+                assert!(covered.iter().all(|&c| c == 1));
+            }
+        }
+    }
+
+    #[test]
+    fn spiral_starts_in_the_center() {
+        let tiles = tiles((96, 96), 32, TileOrder::Spiral);
+        assert_eq!(tiles[0], ((32, 32), (64, 64)));
+    }
+
+    #[test]
+    fn hilbert_tiles_follow_each_other() {
+        let tiles = tiles((256, 256), 32, TileOrder::Hilbert);
+        for pair in tiles.windows(2) {
+            let (a, b) = (pair[0].0, pair[1].0);
+            let distance = (a.0 as i32 - b.0 as i32).abs() + (a.1 as i32 - b.1 as i32).abs();
+            assert_eq!(distance, 32);
+        }
+    }
+}
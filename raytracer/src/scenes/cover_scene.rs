@@ -2,10 +2,38 @@ const RENDER_SETTINGS: RenderSettings = RenderSettings {
     img_size: (1280, 720),
     samples_per_pixel: 256,
     max_depth: 16,
+    bounce_limits: BounceLimits {
+        diffuse: 4,
+        glossy: 8,
+        transmission: 12,
+        volume: 0,
+    },
+    roulette_depth: 3,
+    sample_pattern: SamplePattern::Sobol,
+    filter: Filter::Mitchell {
+        radius: 2.0,
+        b: 1.0 / 3.0,
+        c: 1.0 / 3.0,
+    },
+    working_space: ColorSpace::Rec709,
+    aovs: &[],
+    tile_size: 32,
+    tile_order: TileOrder::Spiral,
+    seed: 0,
+    adaptive: None,
+    progressive: None,
+    time_budget: None,
+    error_target: None,
+    denoise: None,
+    effects: &[],
+    grade: &[],
 };
 
 const NUM_COMPONENTS: usize = 488;
 const SEED: u64 = 420;
+/// This file's source, hashed into the metadata of EXR renders to tell which
+/// version of the scene they show.
+pub const SOURCE: &str = include_str!("cover_scene.rs");
 
 use image::{codecs::hdr::HdrDecoder};
 use rand::{rngs::StdRng, Rng, SeedableRng};
@@ -13,20 +41,30 @@ use std::{convert::TryInto, fs::File, io::BufReader};
 
 use crate::{
     render::{
-        camera::Camera,
+        camera::{
+            f_stop_for_lens, unit_exposure_iso, Aperture, Camera, CameraSettings, Focus, Fov,
+            Projection, Stereo,
+        },
+        filter::Filter,
         materials::{environment::*, material::Material, *},
+        sample_generator::SamplePattern,
         scene::Scene,
         shapes::Shape,
+        tiles::TileOrder,
+    },
+    scenes::{BounceLimits, RenderSettings},
+    utils::{
+        color_space::{transform, ColorSpace},
+        ray::Ray,
+        vector::*,
     },
-    scenes::RenderSettings,
-    utils::{ray::Ray, vector::*},
 };
 
 pub fn generate() -> Scene<'static, NUM_COMPONENTS> {
     // Environment setup
     let environment = Environment::DefaultSkyEnvironment {};
     // Environment::HDRIEnvironment {
-    //     texture: &fetch_hdr("tex/sky4.hdr"),
+    //     texture: &fetch_hdr("tex/sky4.hdr", ColorSpace::Rec709),
     //     size: (4096, 2048),
     //     brightness: 1.0,
     // };
@@ -136,18 +174,39 @@ pub fn generate() -> Scene<'static, NUM_COMPONENTS> {
     let origin = Point3::new(13.0, 2.0, 3.0);
     let lookat = Point3::new(0.0, 0.0, 0.0);
     let vfov = 20.0;
-    let aperture = 0.1;
-    let focal_point = Ray::new(origin, lookat - origin).at(10.0);
+    let projection = Projection::Perspective {
+        fov: Fov::Vertical(vfov),
+    };
+    // a 0.1 wide lens without any exposure scaling, like the original camera
+    let f_stop = f_stop_for_lens(vfov, 0.1);
+    let shutter_speed = 1.0;
+    let shutter = (0.0, 1.0);
+    let focal_point = Ray::new(origin, lookat - origin, shutter.0).at(10.0);
     let aspect_ratio = RENDER_SETTINGS.img_size.0 as f32 / RENDER_SETTINGS.img_size.1 as f32;
 
-    let camera = Camera::new(origin, lookat, vfov, aspect_ratio, aperture, focal_point);
-
-    let scene = Scene {
-        components: components_array,
-        camera,
-        environment,
-        render_settings: RENDER_SETTINGS,
-    };
+    let camera = Camera::new(CameraSettings {
+        origin,
+        lookat,
+        vup: Vec3::new(0.0, 1.0, 0.0),
+        roll: 0.0,
+        projection,
+        aspect_ratio,
+        f_stop,
+        focus: Focus::Point(focal_point),
+        shutter,
+        shutter_speed,
+        iso: unit_exposure_iso(f_stop, shutter_speed),
+        white_balance: 6500.0,
+        working_space: RENDER_SETTINGS.working_space,
+        aperture: Aperture::Circular,
+        cat_eye: 0.0,
+        chromatic_aberration: 0.0,
+        shift: (0.0, 0.0),
+        tilt: (0.0, 0.0),
+        stereo: Stereo::Mono,
+    });
+
+    let scene = Scene::new(components_array, environment, camera, RENDER_SETTINGS);
 
     return scene;
 }
@@ -156,7 +215,10 @@ pub fn get_render_settings() -> RenderSettings {
     return RENDER_SETTINGS;
 }
 
-fn fetch_hdr(file_path: &str) -> &'static [Color] {
+/// Loads an HDR image whose colors are in `color_space`, converted to the
+/// working space.
+fn fetch_hdr(file_path: &str, color_space: ColorSpace) -> &'static [Color] {
+    let to_working_space = color_space.conversion_to(RENDER_SETTINGS.working_space);
     let f = File::open(file_path).unwrap();
     let reader = BufReader::new(f);
     let d = HdrDecoder::new(reader).unwrap();
@@ -164,7 +226,8 @@ fn fetch_hdr(file_path: &str) -> &'static [Color] {
     let mut vec2: Vec<Color> = Vec::new();
     for c in vec {
         let b = c.clone();
-        vec2.push(Color::new(b.0[0] as f32, b.0[1] as f32, b.0[2] as f32));
+        let c = Color::new(b.0[0] as f32, b.0[1] as f32, b.0[2] as f32);
+        vec2.push(transform(&to_working_space, c));
     }
 
     let static_ref: &'static [Color] = vec2.leak();
@@ -1,8 +1,167 @@
 pub mod cover_scene;
 pub mod scene1;
 
+use crate::render::{
+    aov::Aov, filter::Filter, materials::material::Lobe, sample_generator::SamplePattern,
+    tiles::TileOrder,
+};
+use crate::{
+    post::{effects::Effect, grade::GradeStep},
+    utils::color_space::ColorSpace,
+};
+
 pub struct RenderSettings {
     pub img_size: (u32, u32),
+    /// Samples taken in every pixel, unless `adaptive` is set.
     pub samples_per_pixel: u32,
+    /// Most bounces a path can take in total.
     pub max_depth: u32,
+    pub bounce_limits: BounceLimits,
+    /// Bounces after which dim paths get terminated at random (Russian
+    /// roulette), with the survivors brightened to compensate.
+    pub roulette_depth: u32,
+    pub sample_pattern: SamplePattern,
+    pub filter: Filter,
+    /// Linear color space the scene's colors are given in and light is
+    /// computed in.
+    pub working_space: ColorSpace,
+    /// Extra images to render next to the beauty pass.
+    pub aovs: &'static [Aov],
+    /// Width and height of the square tiles the image is rendered in.
+    pub tile_size: u32,
+    pub tile_order: TileOrder,
+    /// Every random number in the render derives from this, so renders with
+    /// the same settings are bit-identical.
+    pub seed: u32,
+    pub adaptive: Option<AdaptiveSampling>,
+    pub progressive: Option<Progressive>,
+    /// Stops rendering after this many seconds, sizing the last pass to fit.
+    pub time_budget: Option<f32>,
+    /// Stops rendering once the mean relative error of the pixels drops
+    /// below this.
+    pub error_target: Option<f32>,
+    /// Denoises the finished render, which also renders the albedo, normal
+    /// and depth AOVs it needs.
+    pub denoise: Option<Denoise>,
+    /// Lens and film effects applied to the final image before tonemapping,
+    /// in order.
+    pub effects: &'static [Effect],
+    /// Color grade applied to the tonemapped image, step by step.
+    pub grade: &'static [GradeStep],
+}
+
+impl RenderSettings {
+    /// Most samples any pixel can get.
+    pub fn max_samples(&self) -> u32 {
+        match self.adaptive {
+            Some(adaptive) => adaptive.max_samples,
+            None => self.samples_per_pixel,
+        }
+    }
+
+    /// Panics on settings the renderer can't work with, so a render fails
+    /// before it starts rather than hanging.
+    pub fn check(&self) {
+        if let Some(progressive) = self.progressive {
+            if progressive.samples_per_pass == 0 {
+                panic!("progressive passes need at least 1 sample per pixel");
+            }
+        }
+    }
+
+    /// AOVs the film collects: the ones asked for, plus the features the
+    /// denoiser needs.
+    pub fn film_aovs(&self) -> Vec<Aov> {
+        let mut aovs = self.aovs.to_vec();
+        if self.denoise.is_some() {
+            for aov in [Aov::Albedo, Aov::Normal, Aov::Depth] {
+                if !aovs.contains(&aov) {
+                    aovs.push(aov);
+                }
+            }
+        }
+        aovs
+    }
+}
+
+/// 64-bit FNV-1a hash of a scene's source, identifying the exact scene a
+/// render was made from.
+pub fn source_hash(source: &str) -> u64 {
+    source.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Keeps sampling a pixel only while its estimated error is high.
+#[derive(Copy, Clone)]
+pub struct AdaptiveSampling {
+    pub min_samples: u32,
+    pub max_samples: u32,
+    /// Relative standard error of a pixel's luminance below which it stops.
+    pub threshold: f32,
+}
+
+/// Renders the image in passes of a few samples per pixel and writes a
+/// preview in between.
+#[derive(Copy, Clone)]
+pub struct Progressive {
+    pub samples_per_pass: u32,
+    /// Seconds to wait at least between previews. Zero writes one after
+    /// every pass.
+    pub preview_interval: f32,
+}
+
+/// Edge-avoiding wavelet denoiser, see `post::denoise`. Larger sigmas let
+/// more dissimilar neighbours through, blurring more.
+#[derive(Copy, Clone)]
+pub struct Denoise {
+    /// Filter passes; each one reaches twice as far as the last.
+    pub iterations: u32,
+    /// Luminance difference tolerated, in standard deviations of the noise.
+    pub sigma_luminance: f32,
+    /// Exponent on the cosine between normals; higher is stricter.
+    pub sigma_normal: f32,
+    /// Depth difference tolerated, relative to the local depth slope.
+    pub sigma_depth: f32,
+    pub sigma_albedo: f32,
+    /// Also writes the image as rendered, before denoising.
+    pub keep_raw: bool,
+}
+
+/// Most bounces of each kind a path can take.
+#[derive(Copy, Clone)]
+pub struct BounceLimits {
+    pub diffuse: u32,
+    pub glossy: u32,
+    pub transmission: u32,
+    pub volume: u32,
+}
+
+impl BounceLimits {
+    pub fn get(&self, lobe: Lobe) -> u32 {
+        match lobe {
+            Lobe::Diffuse => self.diffuse,
+            Lobe::Glossy => self.glossy,
+            Lobe::Transmission => self.transmission,
+            Lobe::Volume => self.volume,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "at least 1 sample")]
+    fn empty_passes_are_refused() {
+        RenderSettings {
+            progressive: Some(Progressive {
+                samples_per_pass: 0,
+                preview_interval: 0.0,
+            }),
+            ..scene1::RENDER_SETTINGS
+        }
+        .check();
+    }
 }
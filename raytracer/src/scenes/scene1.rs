@@ -2,22 +2,62 @@ pub const RENDER_SETTINGS: RenderSettings = RenderSettings {
     img_size: (600, 400),
     samples_per_pixel: 1024,
     max_depth: 16,
+    bounce_limits: BounceLimits {
+        diffuse: 4,
+        glossy: 8,
+        transmission: 12,
+        volume: 0,
+    },
+    roulette_depth: 3,
+    sample_pattern: SamplePattern::Sobol,
+    filter: Filter::Mitchell {
+        radius: 2.0,
+        b: 1.0 / 3.0,
+        c: 1.0 / 3.0,
+    },
+    working_space: ColorSpace::Rec709,
+    aovs: &[],
+    tile_size: 32,
+    tile_order: TileOrder::Spiral,
+    seed: 0,
+    adaptive: None,
+    progressive: Some(Progressive {
+        samples_per_pass: 64,
+        preview_interval: 10.0,
+    }),
+    time_budget: None,
+    error_target: None,
+    denoise: None,
+    effects: &[],
+    grade: &[],
 };
 
-const NUM_COMPONENTS: usize = 2;
+const NUM_COMPONENTS: usize = 3;
+/// This file's source, hashed into the metadata of EXR renders to tell which
+/// version of the scene they show.
+pub const SOURCE: &str = include_str!("scene1.rs");
 
 use image::codecs::hdr::HdrDecoder;
 use std::{fs::File, io::BufReader, mem};
 
 use crate::{
     render::{
-        camera::Camera,
+        camera::{
+            f_stop_for_lens, unit_exposure_iso, Aperture, Camera, CameraSettings, Focus, Fov,
+            Projection, Stereo,
+        },
+        filter::Filter,
         materials::{environment::*, material::Material, *},
+        sample_generator::SamplePattern,
         scene::Scene,
         shapes::Shape,
+        tiles::TileOrder,
+    },
+    scenes::{BounceLimits, Progressive, RenderSettings},
+    utils::{
+        color_space::{transform, ColorSpace},
+        vector::*,
     },
-    scenes::RenderSettings,
-    utils::vector::*,
 };
 
 pub fn generate<'a>() -> Scene<'a, NUM_COMPONENTS> {
@@ -25,13 +65,14 @@ pub fn generate<'a>() -> Scene<'a, NUM_COMPONENTS> {
     let environment = //Environment::DefaultSkyEnvironment {};
 
     Environment::HDRIEnvironment {
-        texture: &fetch_hdr("tex/sky4.hdr"),
+        texture: &fetch_hdr("tex/sky4.hdr", ColorSpace::Rec709),
         size: (4096, 2048), // 8388608
         brightness: 1.0,
     };
 
     // Components
     let center = Point3::new(0.0, -0.05, -1.0);
+    let shutter = (0.0, 1.0);
 
     let components: [Shape; NUM_COMPONENTS] = [
         // Glass Ball
@@ -43,6 +84,15 @@ pub fn generate<'a>() -> Scene<'a, NUM_COMPONENTS> {
                 ior: 1.33,
             },
         },
+        // Rolling Ball, blurred by the open shutter
+        Shape::MovingSphere {
+            center: (Point3::new(-0.8, -0.4, -0.8), Point3::new(-0.65, -0.4, -0.8)),
+            time: shutter,
+            radius: 0.1,
+            material: Material::Lambertian {
+                albedo: Color::new(0.8, 0.3, 0.1),
+            },
+        },
         // Ground
         Shape::Sphere {
             center: Point3::new(0.0, -100.5, -1.0),
@@ -57,20 +107,40 @@ pub fn generate<'a>() -> Scene<'a, NUM_COMPONENTS> {
     let origin = Point3::new(0.0, 0.125, 1.0);
     let lookat = center;
     let vfov = 50.0;
-    let aperture = 0.025;
+    let projection = Projection::Perspective {
+        fov: Fov::Vertical(vfov),
+    };
+    // a 0.025 wide lens without any exposure scaling, like the original camera
+    let f_stop = f_stop_for_lens(vfov, 0.025);
+    let shutter_speed = 1.0;
     let focal_point = Point3::new(0.0, -0.55, -1.0);
     let aspect_ratio = RENDER_SETTINGS.img_size.0 as f32 / RENDER_SETTINGS.img_size.1 as f32;
 
-    let camera = Camera::new(origin, lookat, vfov, aspect_ratio, aperture, focal_point);
+    let camera = Camera::new(CameraSettings {
+        origin,
+        lookat,
+        vup: Vec3::new(0.0, 1.0, 0.0),
+        roll: 0.0,
+        projection,
+        aspect_ratio,
+        f_stop,
+        focus: Focus::Point(focal_point),
+        shutter,
+        shutter_speed,
+        iso: unit_exposure_iso(f_stop, shutter_speed),
+        white_balance: 6500.0,
+        working_space: RENDER_SETTINGS.working_space,
+        aperture: Aperture::Circular,
+        cat_eye: 0.0,
+        chromatic_aberration: 0.0,
+        shift: (0.0, 0.0),
+        tilt: (0.0, 0.0),
+        stereo: Stereo::Mono,
+    });
 
     // Scene
 
-    let scene = Scene {
-        components,
-        camera,
-        environment,
-        render_settings: RENDER_SETTINGS,
-    };
+    let scene = Scene::new(components, environment, camera, RENDER_SETTINGS);
 
     return scene;
 }
@@ -79,7 +149,10 @@ pub fn get_render_settings() -> RenderSettings {
     return RENDER_SETTINGS;
 }
 
-fn fetch_hdr(file_path: &str) -> &'static [Color] {
+/// Loads an HDR image whose colors are in `color_space`, converted to the
+/// working space.
+fn fetch_hdr(file_path: &str, color_space: ColorSpace) -> &'static [Color] {
+    let to_working_space = color_space.conversion_to(RENDER_SETTINGS.working_space);
     let f = File::open(file_path).unwrap();
     let reader = BufReader::new(f);
     let d = HdrDecoder::new(reader).unwrap();
@@ -87,7 +160,8 @@ fn fetch_hdr(file_path: &str) -> &'static [Color] {
     let mut vec2: Vec<Color> = Vec::new();
     for c in vec {
         let b = c.clone();
-        vec2.push(Color::new(b.0[0], b.0[1], b.0[2]));
+        let c = Color::new(b.0[0], b.0[1], b.0[2]);
+        vec2.push(transform(&to_working_space, c));
     }
 
     let static_ref: &'static [Color] = vec2.leak();
@@ -0,0 +1,103 @@
+use super::{
+    color_space::{transform, ColorSpace},
+    vector::*,
+};
+
+/// Temperature whose white balance leaves colors unchanged; close to D65,
+/// the white point of Rec.709 and Rec.2020.
+const NEUTRAL_TEMPERATURE: f32 = 6500.0;
+/// Least a channel of the light being balanced counts as, relative to its
+/// unit luminance. Blackbodies below about 2000K have no blue in Rec.709, and
+/// dividing by it would give huge or negative gains.
+const MIN_CHANNEL: f32 = 0.01;
+
+/// Relative luminance of a linear color in `space`.
+pub fn luminance(c: Color, space: ColorSpace) -> f32 {
+    dot(space.luma_weights(), c)
+}
+
+/// CIE 1931 xy chromaticity of a blackbody at `kelvin`, using the cubic fit by
+/// Kim et al. (2002). Valid from 1667K to 25000K; clamped outside that range.
+pub fn planckian_xy(kelvin: f32) -> (f32, f32) {
+    let t = kelvin.clamp(1667.0, 25000.0);
+    let (t2, t3) = (t * t, t * t * t);
+
+    let x = if t <= 4000.0 {
+        -0.2661239e9 / t3 - 0.2343589e6 / t2 + 0.8776956e3 / t + 0.179910
+    } else {
+        -3.0258469e9 / t3 + 2.107038e6 / t2 + 0.2226347e3 / t + 0.240390
+    };
+
+    let (x2, x3) = (x * x, x * x * x);
+    let y = if t <= 2222.0 {
+        -1.1063814 * x3 - 1.3481102 * x2 + 2.1855583 * x - 0.20219683
+    } else if t <= 4000.0 {
+        -0.9549476 * x3 - 1.3741859 * x2 + 2.09137 * x - 0.16748867
+    } else {
+        3.081758 * x3 - 5.873387 * x2 + 3.75113 * x - 0.37001483
+    };
+
+    (x, y)
+}
+
+/// Color of a blackbody at `kelvin` with unit luminance, in `space`.
+pub fn temperature_to_rgb(kelvin: f32, space: ColorSpace) -> Color {
+    let (x, y) = planckian_xy(kelvin);
+    let xyz = Vec3::new(x / y, 1.0, (1.0 - x - y) / y);
+    transform(&space.xyz_to_rgb(), xyz)
+}
+
+/// Per-channel gains that make light of `kelvin` render as neutral, like a
+/// camera's white balance setting. Green is kept fixed so brightness barely
+/// changes, and `NEUTRAL_TEMPERATURE` gives no change at all. The gains
+/// apply to colors in `space` and are never negative.
+pub fn white_balance_gains(kelvin: f32, space: ColorSpace) -> Color {
+    let light = temperature_to_rgb(kelvin, space);
+    let light = Color::new(
+        light.x.max(MIN_CHANNEL),
+        light.y.max(MIN_CHANNEL),
+        light.z.max(MIN_CHANNEL),
+    );
+    let gains = temperature_to_rgb(NEUTRAL_TEMPERATURE, space) / light;
+    gains / gains.y
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPACES: [ColorSpace; 3] = [ColorSpace::Rec709, ColorSpace::AcesCg, ColorSpace::Rec2020];
+
+    #[test]
+    fn white_has_unit_luminance() {
+        for space in SPACES {
+            assert!((luminance(Color::new(1.0, 1.0, 1.0), space) - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn neutral_temperature_leaves_colors_unchanged() {
+        for space in SPACES {
+            let gains = white_balance_gains(NEUTRAL_TEMPERATURE, space);
+            assert!((gains - Color::new(1.0, 1.0, 1.0)).length() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn gains_stay_positive_and_finite() {
+        for space in SPACES {
+            for &kelvin in &[500.0, 1000.0, 2000.0, 3200.0, 10000.0, 40000.0] {
+                let gains = white_balance_gains(kelvin, space);
+                for g in [gains.x, gains.y, gains.z] {
+                    assert!(g.is_finite() && g > 0.0, "{}K", kelvin);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn warm_light_is_cooled_down() {
+        let gains = white_balance_gains(3200.0, ColorSpace::Rec709);
+        assert!(gains.z > gains.y && gains.y > gains.x);
+    }
+}
@@ -0,0 +1,279 @@
+use super::vector::*;
+
+/// Bradford cone response matrix, for adapting colors between white points.
+const BRADFORD: [Vec3; 3] = [
+    Vec3 {
+        x: 0.8951,
+        y: 0.2664,
+        z: -0.1614,
+    },
+    Vec3 {
+        x: -0.7502,
+        y: 1.7135,
+        z: 0.0367,
+    },
+    Vec3 {
+        x: 0.0389,
+        y: -0.0685,
+        z: 1.0296,
+    },
+];
+
+/// CIE 1931 xy chromaticities of the D65 and ACES white points.
+const D65: (f32, f32) = (0.3127, 0.3290);
+const ACES_WHITE: (f32, f32) = (0.32168, 0.33767);
+
+/// * Rec709
+/// * AcesCg
+/// * Rec2020
+///
+/// Linear RGB color space, defined by the chromaticities of its primaries
+/// and white point. Rendering happens in one of these, the working space;
+/// scene colors are given in it and everything read or written gets
+/// converted from or to it.
+#[allow(dead_code)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum ColorSpace {
+    /// Primaries of sRGB and HD video, white point D65.
+    Rec709,
+    /// ACES AP1 primaries, white point near D60. Wider than Rec.709, which
+    /// makes saturated colors mix more like they do in reality.
+    AcesCg,
+    /// Primaries of UHD video, white point D65.
+    Rec2020,
+}
+
+impl ColorSpace {
+    /// xy chromaticities of red, green, blue and white.
+    pub fn chromaticities(self) -> [(f32, f32); 4] {
+        match self {
+            ColorSpace::Rec709 => [(0.64, 0.33), (0.30, 0.60), (0.15, 0.06), D65],
+            ColorSpace::AcesCg => [(0.713, 0.293), (0.165, 0.830), (0.128, 0.044), ACES_WHITE],
+            ColorSpace::Rec2020 => [(0.708, 0.292), (0.170, 0.797), (0.131, 0.046), D65],
+        }
+    }
+
+    /// Matrix from this space to CIE XYZ, scaled so white has Y = 1.
+    pub fn to_xyz(self) -> [Vec3; 3] {
+        let [r, g, b, white] = self.chromaticities();
+        let primaries = transpose([xy_to_xyz(r), xy_to_xyz(g), xy_to_xyz(b)]);
+        // scale each primary so together they add up to white
+        let s = transform(&inverse(primaries), xy_to_xyz(white));
+        let scale = |row: Vec3| row * s;
+        [
+            scale(primaries[0]),
+            scale(primaries[1]),
+            scale(primaries[2]),
+        ]
+    }
+
+    /// Matrix from CIE XYZ to this space.
+    pub fn xyz_to_rgb(self) -> [Vec3; 3] {
+        inverse(self.to_xyz())
+    }
+
+    /// Contributions of red, green and blue to luminance: the Y row of
+    /// `to_xyz`.
+    pub fn luma_weights(self) -> Vec3 {
+        self.to_xyz()[1]
+    }
+
+    /// Matrix converting colors from this space to `other`, adapting white
+    /// to white with the Bradford transform.
+    pub fn conversion_to(self, other: ColorSpace) -> [Vec3; 3] {
+        if self == other {
+            return IDENTITY;
+        }
+        let white = |space: ColorSpace| {
+            let white = xy_to_xyz(space.chromaticities()[3]);
+            transform(&BRADFORD, white)
+        };
+        let gains = white(other) / white(self);
+        let adapt = multiply(&inverse(BRADFORD), &multiply(&diagonal(gains), &BRADFORD));
+        multiply(&inverse(other.to_xyz()), &multiply(&adapt, &self.to_xyz()))
+    }
+}
+
+/// * Linear
+/// * Srgb
+/// * Rec709
+/// * Gamma(f32)
+///
+/// Nonlinear encoding of color values, for storing them in few bits.
+#[allow(dead_code)]
+#[derive(Copy, Clone)]
+pub enum TransferFunction {
+    Linear,
+    /// The piecewise sRGB curve, for computer displays.
+    Srgb,
+    /// The BT.709 camera curve, used for video.
+    Rec709,
+    /// A pure power curve with the given exponent.
+    Gamma(f32),
+}
+
+impl TransferFunction {
+    /// Linear light to encoded value.
+    pub fn encode(&self, v: f32) -> f32 {
+        match *self {
+            TransferFunction::Linear => v,
+            TransferFunction::Srgb => {
+                if v <= 0.0031308 {
+                    12.92 * v
+                } else {
+                    1.055 * v.powf(1.0 / 2.4) - 0.055
+                }
+            }
+            TransferFunction::Rec709 => {
+                if v < 0.018 {
+                    4.5 * v
+                } else {
+                    1.099 * v.powf(0.45) - 0.099
+                }
+            }
+            TransferFunction::Gamma(gamma) => v.max(0.0).powf(1.0 / gamma),
+        }
+    }
+
+    /// Encoded value to linear light.
+    pub fn decode(&self, v: f32) -> f32 {
+        match *self {
+            TransferFunction::Linear => v,
+            TransferFunction::Srgb => {
+                if v <= 0.04045 {
+                    v / 12.92
+                } else {
+                    ((v + 0.055) / 1.055).powf(2.4)
+                }
+            }
+            TransferFunction::Rec709 => {
+                if v < 0.081 {
+                    v / 4.5
+                } else {
+                    ((v + 0.099) / 1.099).powf(1.0 / 0.45)
+                }
+            }
+            TransferFunction::Gamma(gamma) => v.max(0.0).powf(gamma),
+        }
+    }
+
+    pub fn encode_color(&self, c: Color) -> Color {
+        Color::new(self.encode(c.x), self.encode(c.y), self.encode(c.z))
+    }
+}
+
+const IDENTITY: [Vec3; 3] = [
+    Vec3 {
+        x: 1.0,
+        y: 0.0,
+        z: 0.0,
+    },
+    Vec3 {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+    },
+    Vec3 {
+        x: 0.0,
+        y: 0.0,
+        z: 1.0,
+    },
+];
+
+/// Applies a matrix, given as its rows, to a color.
+pub fn transform(m: &[Vec3; 3], c: Color) -> Color {
+    Color::new(dot(m[0], c), dot(m[1], c), dot(m[2], c))
+}
+
+fn xy_to_xyz((x, y): (f32, f32)) -> Vec3 {
+    Vec3::new(x / y, 1.0, (1.0 - x - y) / y)
+}
+
+fn diagonal(v: Vec3) -> [Vec3; 3] {
+    [
+        Vec3::new(v.x, 0.0, 0.0),
+        Vec3::new(0.0, v.y, 0.0),
+        Vec3::new(0.0, 0.0, v.z),
+    ]
+}
+
+fn transpose(m: [Vec3; 3]) -> [Vec3; 3] {
+    [
+        Vec3::new(m[0].x, m[1].x, m[2].x),
+        Vec3::new(m[0].y, m[1].y, m[2].y),
+        Vec3::new(m[0].z, m[1].z, m[2].z),
+    ]
+}
+
+fn multiply(a: &[Vec3; 3], b: &[Vec3; 3]) -> [Vec3; 3] {
+    let columns = transpose(*b);
+    let row = |r: Vec3| Vec3::new(dot(r, columns[0]), dot(r, columns[1]), dot(r, columns[2]));
+    [row(a[0]), row(a[1]), row(a[2])]
+}
+
+fn inverse(m: [Vec3; 3]) -> [Vec3; 3] {
+    // the cross products of pairs of rows are the columns of the adjugate
+    let det = dot(m[0], cross(m[1], m[2]));
+    let columns = [cross(m[1], m[2]), cross(m[2], m[0]), cross(m[0], m[1])];
+    let inv = transpose(columns);
+    [inv[0] / det, inv[1] / det, inv[2] / det]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPACES: [ColorSpace; 3] = [ColorSpace::Rec709, ColorSpace::AcesCg, ColorSpace::Rec2020];
+
+    fn assert_close(a: Color, b: Color, tolerance: f32) {
+        assert!((a - b).length() < tolerance);
+    }
+
+    #[test]
+    fn conversions_round_trip() {
+        let c = Color::new(0.2, 0.5, 0.9);
+        for a in SPACES {
+            for b in SPACES {
+                let there = transform(&a.conversion_to(b), c);
+                assert_close(transform(&b.conversion_to(a), there), c, 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn white_converts_to_white() {
+        let white = Color::new(1.0, 1.0, 1.0);
+        for a in SPACES {
+            for b in SPACES {
+                assert_close(transform(&a.conversion_to(b), white), white, 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn luma_weights_add_up_to_one() {
+        for space in SPACES {
+            let w = space.luma_weights();
+            assert!((w.x + w.y + w.z - 1.0).abs() < 1e-5);
+        }
+        let rec709 = ColorSpace::Rec709.luma_weights();
+        assert_close(rec709, Vec3::new(0.2126, 0.7152, 0.0722), 1e-4);
+    }
+
+    #[test]
+    fn transfer_functions_round_trip() {
+        let functions = [
+            TransferFunction::Linear,
+            TransferFunction::Srgb,
+            TransferFunction::Rec709,
+            TransferFunction::Gamma(2.2),
+        ];
+        for function in functions {
+            for i in 0..=100 {
+                let v = i as f32 / 100.0;
+                assert!((function.decode(function.encode(v)) - v).abs() < 1e-5);
+            }
+        }
+        assert!((TransferFunction::Srgb.encode(0.18) - 0.4614).abs() < 1e-4);
+    }
+}
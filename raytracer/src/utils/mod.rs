@@ -1,2 +1,5 @@
+pub mod aabb;
+pub mod color;
+pub mod color_space;
 pub mod vector;
-pub mod ray;
\ No newline at end of file
+pub mod ray;
@@ -3,13 +3,15 @@ use super::vector::*;
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    pub time: f32,
 }
 
 impl Ray {
-    pub fn new(origin: Vec3, direction: Vec3) -> Ray {
+    pub fn new(origin: Vec3, direction: Vec3, time: f32) -> Ray {
         Ray {
             origin: origin,
-            direction: direction.unit()
+            direction: direction.unit(),
+            time,
         }
     }
 